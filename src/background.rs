@@ -0,0 +1,360 @@
+use crate::{
+    lights::LightSample,
+    sampling::{self, Distribution1D},
+    utils,
+};
+use image::codecs::hdr::HdrDecoder;
+use nalgebra::{Vector2, Vector3};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+// light arriving from infinitely far away along rays that miss the scene
+pub enum Background {
+    Color(Vector3<f64>),
+    // blends from the bottom color for rays going down to the top color for rays going up
+    Gradient {
+        bottom: Vector3<f64>,
+        top: Vector3<f64>,
+    },
+    Environment(EnvironmentMap),
+}
+
+impl Background {
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        match self {
+            Background::Color(color) => *color,
+            Background::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.y + 1.);
+                (1. - t) * bottom + t * top
+            }
+            Background::Environment(map) => map.radiance(direction),
+        }
+    }
+
+    // pick a direction for lighting a point, environment maps are importance sampled
+    pub fn sample(&self, u: &Vector2<f64>) -> Option<LightSample> {
+        let (direction, pdf) = match self {
+            Background::Environment(map) => map.sample(u)?,
+            _ => (sampling::sample_uniform_sphere(u), 1. / (4. * PI)),
+        };
+        Some(LightSample {
+            position: None,
+            direction,
+            radiance: self.radiance(&direction),
+            pdf,
+            delta: false,
+        })
+    }
+
+    // solid angle density of sample producing the direction
+    pub fn pdf(&self, direction: &Vector3<f64>) -> f64 {
+        match self {
+            Background::Environment(map) => map.pdf(direction),
+            _ => 1. / (4. * PI),
+        }
+    }
+}
+
+// equirectangular image of the light around the scene. the y axis points to the top row and
+// the center of the image is seen when looking down the -z axis
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    // rows from top to bottom
+    pixels: Vec<Vector3<f64>>,
+    // density over the columns of every row and over the rows
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl EnvironmentMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vector3<f64>>) -> Self {
+        assert!(
+            width > 0 && height > 0 && pixels.len() == width * height,
+            "environment map of {width}x{height} pixels needs {} values, got {}",
+            width * height,
+            pixels.len()
+        );
+
+        // sample bright pixels more often, rows near the poles cover a smaller solid angle
+        let conditional: Vec<Distribution1D> = pixels
+            .chunks(width)
+            .enumerate()
+            .map(|(row, pixels)| {
+                let sin_theta = (PI * (row as f64 + 0.5) / height as f64).sin();
+                Distribution1D::new(
+                    pixels
+                        .iter()
+                        .map(|p| utils::luminance(p) * sin_theta)
+                        .collect(),
+                )
+            })
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.integral()).collect());
+
+        Self {
+            width,
+            height,
+            pixels,
+            conditional,
+            marginal,
+        }
+    }
+
+    // load a radiance .hdr or a .pfm file, picked by the extension
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hdr") => Self::from_hdr(BufReader::new(File::open(path)?)),
+            Some("pfm") => Self::from_pfm(BufReader::new(File::open(path)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported environment map {}", path.display()),
+            )),
+        }
+    }
+
+    pub fn from_hdr<R: io::BufRead>(reader: R) -> io::Result<Self> {
+        let invalid = |error: image::ImageError| io::Error::new(io::ErrorKind::InvalidData, error);
+        let decoder = HdrDecoder::new(reader).map_err(invalid)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .map_err(invalid)?
+            .into_iter()
+            .map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        Ok(Self::new(
+            metadata.width as usize,
+            metadata.height as usize,
+            pixels,
+        ))
+    }
+
+    // portable float map: a "PF" (rgb) or "Pf" (grey) header, the size, a scale whose sign gives
+    // the byte order and then the rows from bottom to top as 32 bit floats
+    pub fn from_pfm<R: Read>(mut reader: R) -> io::Result<Self> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // the header is four whitespace separated tokens followed by a single whitespace
+        let mut tokens = Vec::new();
+        let mut position = 0;
+        while tokens.len() < 4 {
+            while position < data.len() && data[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            let start = position;
+            while position < data.len() && !data[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                return Err(invalid("truncated pfm header"));
+            }
+            tokens.push(String::from_utf8_lossy(&data[start..position]).into_owned());
+        }
+        position += 1;
+
+        let channels = match tokens[0].as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid("not a pfm file")),
+        };
+        let width: usize = tokens[1]
+            .parse()
+            .map_err(|_| invalid("invalid pfm width"))?;
+        let height: usize = tokens[2]
+            .parse()
+            .map_err(|_| invalid("invalid pfm height"))?;
+        let scale: f64 = tokens[3]
+            .parse()
+            .map_err(|_| invalid("invalid pfm scale"))?;
+
+        if width == 0 || height == 0 {
+            return Err(invalid("empty pfm image"));
+        }
+        // the size comes from the file, a corrupt header must not overflow the byte count
+        let value_count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .filter(|values| values.checked_mul(4).is_some())
+            .ok_or_else(|| invalid("pfm dimensions too large"))?;
+        let body = data.get(position..).unwrap_or_default();
+        if body.len() / 4 < value_count {
+            return Err(invalid("truncated pfm data"));
+        }
+        let values: Vec<f64> = body
+            .chunks_exact(4)
+            .take(value_count)
+            .map(|bytes| {
+                let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                if scale < 0. {
+                    f32::from_le_bytes(bytes) as f64
+                } else {
+                    f32::from_be_bytes(bytes) as f64
+                }
+            })
+            .collect();
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in (0..height).rev() {
+            for column in 0..width {
+                let value = &values[(row * width + column) * channels..][..channels];
+                pixels.push(if channels == 3 {
+                    Vector3::new(value[0], value[1], value[2])
+                } else {
+                    Vector3::new(value[0], value[0], value[0])
+                });
+            }
+        }
+        Ok(Self::new(width, height, pixels))
+    }
+
+    // position in the image with both coordinates in [0, 1]
+    fn direction_to_uv(direction: &Vector3<f64>) -> Vector2<f64> {
+        let phi = direction.x.atan2(-direction.z);
+        let theta = direction.y.clamp(-1., 1.).acos();
+        Vector2::new(0.5 + phi / (2. * PI), theta / PI)
+    }
+
+    fn uv_to_direction(uv: &Vector2<f64>) -> Vector3<f64> {
+        let phi = (uv.x - 0.5) * 2. * PI;
+        let theta = uv.y * PI;
+        Vector3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    fn pixel(&self, uv: &Vector2<f64>) -> (usize, usize) {
+        let column = ((uv.x * self.width as f64) as usize).min(self.width - 1);
+        let row = ((uv.y * self.height as f64) as usize).min(self.height - 1);
+        (column, row)
+    }
+
+    pub fn radiance(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        let (column, row) = self.pixel(&Self::direction_to_uv(direction));
+        self.pixels[row * self.width + column]
+    }
+
+    // direction with a density roughly proportional to the luminance arriving from it
+    pub fn sample(&self, u: &Vector2<f64>) -> Option<(Vector3<f64>, f64)> {
+        let (y, row_pdf, row) = self.marginal.sample(u.y);
+        let (x, column_pdf, _) = self.conditional[row].sample(u.x);
+        let uv = Vector2::new(x, y);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0. {
+            return None;
+        }
+        // convert the density over the image to solid angle
+        let pdf = row_pdf * column_pdf / (2. * PI * PI * sin_theta);
+        Some((Self::uv_to_direction(&uv), pdf))
+    }
+
+    pub fn pdf(&self, direction: &Vector3<f64>) -> f64 {
+        let uv = Self::direction_to_uv(direction);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        let (column, row) = self.pixel(&uv);
+        self.marginal.pdf(row) * self.conditional[row].pdf(column) / (2. * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header and rows from bottom to top, little endian as marked by the negative scale
+    fn pfm_bytes(magic: &str, width: usize, height: usize, values: &[f32]) -> Vec<u8> {
+        let mut bytes = format!("{magic}\n{width} {height}\n-1.0\n").into_bytes();
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn error_message(bytes: &[u8]) -> String {
+        match EnvironmentMap::from_pfm(bytes) {
+            Ok(_) => panic!("loading should fail"),
+            Err(error) => {
+                assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+                error.to_string()
+            }
+        }
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let values = [
+            0.25, 0.5, 1., 2., 4., 8., // bottom row
+            0., 0.125, 16., 3., 1.5, 0.75, // top row
+        ];
+        let map = EnvironmentMap::from_pfm(&pfm_bytes("PF", 2, 2, &values)[..]).unwrap();
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(
+            map.pixels,
+            vec![
+                Vector3::new(0., 0.125, 16.),
+                Vector3::new(3., 1.5, 0.75),
+                Vector3::new(0.25, 0.5, 1.),
+                Vector3::new(2., 4., 8.),
+            ]
+        );
+
+        // big endian grey values are copied into every channel
+        let mut bytes = b"Pf 1 2 1.0\n".to_vec();
+        for value in [0.5f32, 6.] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        let map = EnvironmentMap::from_pfm(&bytes[..]).unwrap();
+        assert_eq!(
+            map.pixels,
+            vec![Vector3::new(6., 6., 6.), Vector3::new(0.5, 0.5, 0.5)]
+        );
+    }
+
+    #[test]
+    fn pfm_truncated() {
+        let bytes = pfm_bytes("PF", 2, 2, &[1.; 12]);
+        assert_eq!(
+            error_message(&bytes[..bytes.len() - 1]),
+            "truncated pfm data"
+        );
+        assert_eq!(
+            error_message(&pfm_bytes("PF", 2, 2, &[])),
+            "truncated pfm data"
+        );
+        assert_eq!(error_message(b"PF\n2 2"), "truncated pfm header");
+        assert_eq!(error_message(b""), "truncated pfm header");
+    }
+
+    #[test]
+    fn pfm_bad_header() {
+        assert_eq!(
+            error_message(&pfm_bytes("P6", 1, 1, &[1.; 3])),
+            "not a pfm file"
+        );
+        assert_eq!(error_message(b"PF x 1 -1.0\n"), "invalid pfm width");
+        assert_eq!(error_message(b"PF 1 -1 -1.0\n"), "invalid pfm height");
+        assert_eq!(error_message(b"PF 1 1 scale\n"), "invalid pfm scale");
+        assert_eq!(
+            error_message(&pfm_bytes("PF", 0, 1, &[])),
+            "empty pfm image"
+        );
+        assert_eq!(
+            error_message(format!("PF {} 2 -1.0\n", usize::MAX).as_bytes()),
+            "pfm dimensions too large"
+        );
+    }
+}
@@ -1,134 +1,837 @@
-use crate::datastructures::{AABBNode, BVH};
-use crate::primitives::{HitPoint, Object, Ray, Triangle};
-use nalgebra::{Vector3, Vector4};
+use crate::datastructures::{AlignedBox3d, BvhBuildOptions, BVH};
+use crate::primitives::{
+    HitPoint, Material, MaterialId, Object, Ray, SceneObject, SurfaceSample, Triangle,
+};
+use crate::sampling::Distribution1D;
+use nalgebra::{Vector2, Vector3};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::cmp::Ordering;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
+// what was wrong with a line of a mesh file
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    BadHeader(String),
+    // fewer elements than announced, or too few values on a line
+    WrongCount {
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    IndexOutOfRange {
+        index: i64,
+        count: usize,
+    },
+    InvalidNumber(String),
+    MissingValue,
+    // a format, type or other feature of the file that cannot be loaded
+    Unsupported(String),
+    MissingProperty(String),
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::BadHeader(header) => write!(f, "unknown header {header:?}"),
+            ParseErrorKind::WrongCount {
+                what,
+                expected,
+                found,
+            } => write!(f, "expected {expected} {what} but found {found}"),
+            ParseErrorKind::IndexOutOfRange { index, count } => {
+                write!(f, "index {index} is out of range for {count} elements")
+            }
+            ParseErrorKind::InvalidNumber(token) => write!(f, "{token:?} is not a valid number"),
+            ParseErrorKind::MissingValue => write!(f, "missing value"),
+            ParseErrorKind::Unsupported(what) => write!(f, "unsupported {what}"),
+            ParseErrorKind::MissingProperty(name) => write!(f, "missing property {name:?}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MeshLoadError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // line 0 stands for the end of the file
+    Parse {
+        path: PathBuf,
+        line: usize,
+        kind: ParseErrorKind,
+    },
+    // error in the binary part of a file at the given byte offset
+    Binary {
+        path: PathBuf,
+        offset: usize,
+        kind: ParseErrorKind,
+    },
+}
+
+impl MeshLoadError {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        MeshLoadError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(path: impl Into<PathBuf>, line: usize, kind: ParseErrorKind) -> Self {
+        MeshLoadError::Parse {
+            path: path.into(),
+            line,
+            kind,
+        }
+    }
+
+    pub(crate) fn binary(path: impl Into<PathBuf>, offset: usize, kind: ParseErrorKind) -> Self {
+        MeshLoadError::Binary {
+            path: path.into(),
+            offset,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for MeshLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshLoadError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            MeshLoadError::Parse {
+                path,
+                line: 0,
+                kind,
+            } => {
+                write!(f, "{}: end of file: {kind}", path.display())
+            }
+            MeshLoadError::Parse { path, line, kind } => {
+                write!(f, "{}:{line}: {kind}", path.display())
+            }
+            MeshLoadError::Binary { path, offset, kind } => {
+                write!(f, "{}: byte {offset}: {kind}", path.display())
+            }
+        }
+    }
+}
+
+impl Error for MeshLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MeshLoadError::Io { source, .. } => Some(source),
+            MeshLoadError::Parse { .. } | MeshLoadError::Binary { .. } => None,
+        }
+    }
+}
+
+// problem that does not stop a mesh from loading, the affected data falls back to defaults
+#[derive(Debug)]
+pub enum MeshLoadWarning {
+    // a material library that could not be loaded, its materials become the default one
+    MaterialLibrary(MeshLoadError),
+}
+
+impl fmt::Display for MeshLoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshLoadWarning::MaterialLibrary(error) => {
+                write!(f, "{error}, using the default material")
+            }
+        }
+    }
+}
+
+// largest number of elements reserved up front for a count read from a file header, a corrupt
+// count must not abort the allocation
+const MAX_RESERVED: usize = 1 << 20;
+
+pub(crate) fn parse_token<T: FromStr>(token: Option<&str>) -> Result<T, ParseErrorKind> {
+    let token = token.ok_or(ParseErrorKind::MissingValue)?;
+    token
+        .parse()
+        .map_err(|_| ParseErrorKind::InvalidNumber(token.to_owned()))
+}
+
+pub(crate) fn parse_vector3<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+) -> Result<Vector3<f64>, ParseErrorKind> {
+    Ok(Vector3::new(
+        parse_token(tokens.next())?,
+        parse_token(tokens.next())?,
+        parse_token(tokens.next())?,
+    ))
+}
+
+// named run of consecutive triangles, from the o and g statements of an obj file
+#[derive(Debug, Clone)]
+pub struct MeshGroup {
+    pub name: String,
+    pub triangles: Range<usize>,
+}
+
+// how the normals of the triangles around a vertex are combined into the vertex normal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalWeighting {
+    // by the area of the triangles, cheap but biased towards large triangles
+    Area,
+    // by the angle of the triangles at the vertex, independent of the tessellation
+    Angle,
+}
+
+// indexed triangle mesh, triangles refer to vertices by their index so shared vertices are only
+// stored once
 pub struct Mesh {
-    pub triangles: Vec<Triangle>,
+    pub positions: Vec<Vector3<f64>>,
+    // unit normal of every vertex, interpolated across the triangles for smooth shading
+    pub normals: Vec<Vector3<f64>>,
+    // texture coordinates of every vertex, empty if the file did not have any
+    pub uvs: Vec<Vector2<f64>>,
+    // color of every vertex in [0, 1], empty if the file did not have any
+    pub colors: Vec<Vector3<f64>>,
+    // three vertex indices per triangle, counter clockwise seen from the front
+    pub indices: Vec<[u32; 3]>,
+    pub groups: Vec<MeshGroup>,
+    // named materials of the mesh and the index of the material of every triangle, empty if
+    // the file did not assign any
+    pub materials: Vec<(String, Material)>,
+    pub triangle_materials: Vec<Option<usize>>,
     pub bvh: BVH,
+    // total area and the triangles weighted by their area, built the first time the mesh is
+    // sampled as an area light
+    area_distribution: OnceLock<(f64, Distribution1D)>,
 }
 
 impl Mesh {
-    pub fn new(triangles: Vec<Triangle>) -> Self {
-        let bvh = BVH::new(&triangles);
-        Self { triangles, bvh }
-    }
-
-    pub fn from_off_file(path: &str) -> Self {
-        let file = File::open(path).unwrap();
-        let mut reader = BufReader::new(file);
-        let mut header = String::new();
-        reader.read_line(&mut header).unwrap();
-        assert!(header.starts_with("OFF"));
-
-        let mut line = String::new();
-        reader.read_line(&mut line).unwrap();
-        let tokens: Vec<usize> = line
-            .split_ascii_whitespace()
-            .map(|s| s.parse::<usize>().unwrap())
+    // vertex normals are computed with angle weighting
+    pub fn new(positions: Vec<Vector3<f64>>, indices: Vec<[u32; 3]>) -> Self {
+        Self::with_bvh_options(positions, indices, &BvhBuildOptions::default())
+    }
+
+    pub fn with_bvh_options(
+        positions: Vec<Vector3<f64>>,
+        indices: Vec<[u32; 3]>,
+        options: &BvhBuildOptions,
+    ) -> Self {
+        let bounds: Vec<AlignedBox3d> = indices
+            .iter()
+            .map(|face| {
+                let [a, b, c] = face.map(|idx| positions[idx as usize]);
+                AlignedBox3d::from_triangle(&Triangle::new(a, b, c))
+            })
+            .collect();
+        let mut mesh = Self {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            indices,
+            groups: Vec::new(),
+            materials: Vec::new(),
+            triangle_materials: Vec::new(),
+            bvh: BVH::build(&bounds, options),
+            area_distribution: OnceLock::new(),
+        };
+        mesh.compute_normals(NormalWeighting::Angle);
+        mesh
+    }
+
+    // corners with exactly the same position become one shared vertex
+    pub fn from_triangles(triangles: &[Triangle]) -> Self {
+        let mut positions = Vec::new();
+        let mut vertex_ids: HashMap<[u64; 3], u32> = HashMap::new();
+        let indices = triangles
+            .iter()
+            .map(|triangle| {
+                [triangle.point1, triangle.point2, triangle.point3].map(|point| {
+                    let key = [point.x.to_bits(), point.y.to_bits(), point.z.to_bits()];
+                    *vertex_ids.entry(key).or_insert_with(|| {
+                        positions.push(point);
+                        (positions.len() - 1) as u32
+                    })
+                })
+            })
             .collect();
-        let (num_vertices, num_faces) = (tokens[0], tokens[1]);
+        Self::new(positions, indices)
+    }
 
-        let mut vertices = Vec::new();
-        for _ in 0..num_vertices {
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
-            let vertex: Vec<f64> = line
-                .split_ascii_whitespace()
-                .map(|s| s.parse().unwrap())
-                .collect();
-            vertices.push(Vector3::new(vertex[0], vertex[1], vertex[2]));
-        }
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
 
-        let mut triangles = Vec::new();
-        for _ in 0..num_faces {
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
-            let face: Vec<u32> = line
-                .split_ascii_whitespace()
-                .skip(1)
-                .map(|s| s.parse().unwrap())
+    pub fn triangle(&self, idx: usize) -> Triangle {
+        let [a, b, c] = self.indices[idx];
+        Triangle::new(
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        )
+    }
+
+    fn area_distribution(&self) -> &(f64, Distribution1D) {
+        self.area_distribution.get_or_init(|| {
+            let areas: Vec<f64> = (0..self.triangle_count())
+                .map(|idx| self.triangle(idx).area())
                 .collect();
-            let triangle = Triangle::new(
-                vertices[face[0] as usize],
-                vertices[face[1] as usize],
-                vertices[face[2] as usize],
-            );
-            triangles.push(triangle);
+            (areas.iter().sum(), Distribution1D::new(areas))
+        })
+    }
+
+    // replace the vertex normals by the weighted average of the normals of the adjacent triangles
+    pub fn compute_normals(&mut self, weighting: NormalWeighting) {
+        let mut normals = vec![Vector3::zeros(); self.positions.len()];
+        for face in &self.indices {
+            let corners = face.map(|idx| self.positions[idx as usize]);
+            // the length of the cross product is twice the area of the triangle
+            let area_normal = (corners[1] - corners[0]).cross(&(corners[2] - corners[0]));
+            if area_normal == Vector3::zeros() {
+                continue;
+            }
+            for k in 0..3 {
+                let weight = match weighting {
+                    NormalWeighting::Area => 1.,
+                    NormalWeighting::Angle => {
+                        let to_next = corners[(k + 1) % 3] - corners[k];
+                        let to_previous = corners[(k + 2) % 3] - corners[k];
+                        to_next.angle(&to_previous) / area_normal.norm()
+                    }
+                };
+                normals[face[k] as usize] += weight * area_normal;
+            }
         }
+        self.normals = normals
+            .into_iter()
+            .map(|normal| normal.try_normalize(0.).unwrap_or_else(Vector3::zeros))
+            .collect();
+    }
 
-        Self::new(triangles)
+    // normal interpolated from the vertex normals at the barycentric coordinates of a triangle
+    pub fn interpolated_normal(&self, idx: usize, barycentric: &Vector3<f64>) -> Vector3<f64> {
+        let [a, b, c] = self.indices[idx];
+        let normal = barycentric.x * self.normals[a as usize]
+            + barycentric.y * self.normals[b as usize]
+            + barycentric.z * self.normals[c as usize];
+        normal
+            .try_normalize(0.)
+            .unwrap_or_else(|| self.triangle(idx).normal())
     }
 
-    fn stack_intersect(&self, node: &AABBNode, ray: &Ray) -> Option<HitPoint> {
-        let mut stack = vec![node];
+    // copy of the given triangles with their vertex attributes, groups are dropped
+    fn subset(&self, triangles: &[usize]) -> Self {
+        let mut new_ids: Vec<Option<u32>> = vec![None; self.positions.len()];
+        let mut vertices = Vec::new();
+        let indices = triangles
+            .iter()
+            .map(|&idx| {
+                self.indices[idx].map(|old| {
+                    *new_ids[old as usize].get_or_insert_with(|| {
+                        vertices.push(old as usize);
+                        (vertices.len() - 1) as u32
+                    })
+                })
+            })
+            .collect();
 
-        let mut closest_hit_point = None;
-        let mut closest_t = f64::INFINITY;
+        let mut mesh = Self::new(
+            vertices.iter().map(|&v| self.positions[v]).collect(),
+            indices,
+        );
+        mesh.normals = vertices.iter().map(|&v| self.normals[v]).collect();
+        if !self.uvs.is_empty() {
+            mesh.uvs = vertices.iter().map(|&v| self.uvs[v]).collect();
+        }
+        if !self.colors.is_empty() {
+            mesh.colors = vertices.iter().map(|&v| self.colors[v]).collect();
+        }
+        mesh
+    }
 
-        while let Some(node) = stack.pop() {
-            if !node.bbox.intersects(ray) {
-                continue;
+    // one scene object per material of the mesh. the materials of the mesh are appended to the
+    // scene materials and triangles without a material use the default one
+    pub fn into_scene_objects(
+        self,
+        materials: &mut Vec<Material>,
+        default_material: MaterialId,
+    ) -> Vec<SceneObject> {
+        if self.triangle_materials.is_empty() {
+            return vec![SceneObject::new(Box::new(self), default_material)];
+        }
+
+        let first_id = materials.len();
+        materials.extend(self.materials.iter().map(|(_, material)| material.clone()));
+
+        let mut by_material: Vec<Vec<usize>> = vec![Vec::new(); self.materials.len() + 1];
+        for (idx, material) in self.triangle_materials.iter().enumerate() {
+            by_material[material.map_or(0, |m| m + 1)].push(idx);
+        }
+        by_material
+            .iter()
+            .enumerate()
+            .filter(|(_, indices)| !indices.is_empty())
+            .map(|(slot, indices)| {
+                let material = if slot == 0 {
+                    default_material
+                } else {
+                    first_id + slot - 1
+                };
+                SceneObject::new(Box::new(self.subset(indices)), material)
+            })
+            .collect()
+    }
+
+    // OFF, COFF, NOFF and CNOFF files. comments and blank lines are skipped, polygons are split
+    // into triangle fans and the vertex normals of NOFF files are kept
+    pub fn from_off_file(path: &str) -> Result<Self, MeshLoadError> {
+        let file = File::open(path).map_err(|error| MeshLoadError::io(path, error))?;
+        let mut lines = OffLines {
+            lines: BufReader::new(file).lines().enumerate(),
+            path,
+        };
+
+        // the counts may follow the keyword on the same line
+        let (line_number, header) = lines.next("header lines", 1, 0)?;
+        let (keyword, counts) = header
+            .split_once(char::is_whitespace)
+            .unwrap_or((&header, ""));
+        let has_normals = match keyword {
+            "OFF" | "COFF" => false,
+            "NOFF" | "CNOFF" | "NCOFF" => true,
+            _ => {
+                let kind = ParseErrorKind::BadHeader(keyword.to_owned());
+                return Err(MeshLoadError::parse(path, line_number, kind));
             }
+        };
+        let (line_number, counts) = if counts.trim().is_empty() {
+            lines.next("header lines", 2, 1)?
+        } else {
+            (line_number, counts.to_owned())
+        };
+        let mut tokens = counts.split_ascii_whitespace();
+        let (num_vertices, num_faces): (usize, usize) = parse_token(tokens.next())
+            .and_then(|vertices| Ok((vertices, parse_token(tokens.next())?)))
+            .map_err(|kind| MeshLoadError::parse(path, line_number, kind))?;
 
-            match (node.object_idx, &node.left, &node.right) {
-                (Some(object_idx), _, _) => {
-                    if let Some(hit_point) = self.triangles[object_idx].intersects(ray) {
-                        if hit_point.t < closest_t {
-                            closest_hit_point = Some(hit_point);
-                            closest_t = hit_point.t;
-                        }
-                    }
-                }
-                (_, Some(left), Some(right)) => {
-                    stack.push(left);
-                    stack.push(right);
-                }
-                _ => {}
+        // a file shorter than its counts is reported by the loops
+        let mut vertices = Vec::with_capacity(num_vertices.min(MAX_RESERVED));
+        let mut vertex_normals = Vec::new();
+        for found in 0..num_vertices {
+            let (line_number, line) = lines.next("vertices", num_vertices, found)?;
+            let mut tokens = line.split_ascii_whitespace();
+            // colors after the coordinates are ignored
+            let vertex = parse_vector3(&mut tokens)
+                .and_then(|vertex| {
+                    let normal = if has_normals {
+                        Some(parse_vector3(&mut tokens)?)
+                    } else {
+                        None
+                    };
+                    Ok((vertex, normal))
+                })
+                .map_err(|kind| MeshLoadError::parse(path, line_number, kind))?;
+            vertices.push(vertex.0);
+            vertex_normals.extend(vertex.1);
+        }
+
+        let mut indices = Vec::with_capacity(num_faces.min(MAX_RESERVED));
+        for found in 0..num_faces {
+            let (line_number, line) = lines.next("faces", num_faces, found)?;
+            let corners = parse_off_face(&line, num_vertices)
+                .map_err(|kind| MeshLoadError::parse(path, line_number, kind))?;
+            for i in 1..corners.len() - 1 {
+                indices.push([corners[0], corners[i], corners[i + 1]].map(|idx| idx as u32));
             }
         }
 
-        closest_hit_point
+        let mut mesh = Self::new(vertices, indices);
+        if has_normals {
+            mesh.normals = vertex_normals
+                .iter()
+                .map(|normal| normal.try_normalize(0.).unwrap_or_else(Vector3::zeros))
+                .collect();
+        }
+        Ok(mesh)
     }
 
-    fn recur_intersect(&self, node: &AABBNode, ray: &Ray) -> Option<HitPoint> {
-        if !node.bbox.intersects(ray) {
-            return None;
+    // NOFF with the vertex normals, so they are kept when the file is loaded again
+    pub fn save_as_off(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let has_normals = self.normals.len() == self.positions.len();
+        writeln!(file, "{}", if has_normals { "NOFF" } else { "OFF" })?;
+        writeln!(file, "{} {} 0", self.positions.len(), self.indices.len())?;
+        for (idx, p) in self.positions.iter().enumerate() {
+            if has_normals {
+                let n = self.normals[idx];
+                writeln!(file, "{} {} {} {} {} {}", p.x, p.y, p.z, n.x, n.y, n.z)?;
+            } else {
+                writeln!(file, "{} {} {}", p.x, p.y, p.z)?;
+            }
         }
+        for [a, b, c] in &self.indices {
+            writeln!(file, "3 {a} {b} {c}")?;
+        }
+        file.flush()
+    }
+}
 
-        match (node.object_idx, &node.left, &node.right) {
-            (Some(object_idx), _, _) => self.triangles[object_idx].intersects(ray),
-            (_, Some(left), Some(right)) => {
-                let left_res = self.recur_intersect(left, ray);
-                let right_res = self.recur_intersect(right, ray);
+// the lines of an off file that carry data, with their line numbers
+struct OffLines<'a, L: Iterator<Item = (usize, io::Result<String>)>> {
+    lines: L,
+    path: &'a str,
+}
 
-                left_res
-                    .into_iter()
-                    .chain(right_res.into_iter())
-                    .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(Ordering::Equal))
+impl<L: Iterator<Item = (usize, io::Result<String>)>> OffLines<'_, L> {
+    // the next line that is neither blank nor a comment, running out of lines means the file
+    // has fewer elements of the given kind than expected
+    fn next(
+        &mut self,
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    ) -> Result<(usize, String), MeshLoadError> {
+        for (idx, line) in self.lines.by_ref() {
+            let line = line.map_err(|error| MeshLoadError::io(self.path, error))?;
+            let content = line.split('#').next().unwrap_or_default().trim();
+            if !content.is_empty() {
+                return Ok((idx + 1, content.to_owned()));
             }
-            _ => None,
         }
+        let kind = ParseErrorKind::WrongCount {
+            what,
+            expected,
+            found,
+        };
+        Err(MeshLoadError::parse(self.path, 0, kind))
+    }
+}
+
+// number of corners followed by their indices, anything after that is the color of the face
+fn parse_off_face(line: &str, num_vertices: usize) -> Result<Vec<usize>, ParseErrorKind> {
+    let mut tokens = line.split_ascii_whitespace();
+    let corner_count: usize = parse_token(tokens.next())?;
+    if corner_count < 3 {
+        return Err(ParseErrorKind::WrongCount {
+            what: "face corners",
+            expected: 3,
+            found: corner_count,
+        });
     }
+    (0..corner_count)
+        .map(|_| {
+            let index: usize = parse_token(tokens.next())?;
+            if index >= num_vertices {
+                return Err(ParseErrorKind::IndexOutOfRange {
+                    index: index as i64,
+                    count: num_vertices,
+                });
+            }
+            Ok(index)
+        })
+        .collect()
 }
 
 impl Object for Mesh {
     fn intersects(&self, ray: &Ray) -> Option<HitPoint> {
-        self.stack_intersect(&self.bvh.root, ray)
-        // self.recur_intersect(&self.bvh.root, ray)
-
-        // this is the brute force way of doing it
-        // self.triangles
-        //     .iter()
-        //     .filter_map(|triangle| triangle.intersects(ray))
-        //     .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(Ordering::Equal))
+        let (idx, hit_point) = self.bvh.closest_hit(ray, |idx, ray| {
+            self.triangle(idx)
+                .intersects(ray)
+                .map(|hit_point| (hit_point.t, (idx, hit_point)))
+        })?;
+        let normal = self.interpolated_normal(idx, &hit_point.barycentric);
+        Some(hit_point.with_shading_normal(normal))
     }
 
+    // shading normal at the closest point of the closest triangle, the bvh only answers ray
+    // queries so every triangle is checked
     fn normal(&self, point: &Vector3<f64>) -> Vector3<f64> {
-        // this is not correct, we don't use it, because its very cumbersome to calculate which triangle the point is in and then get the normal from that triangle
-        // we can calculate baricentric coordinates to get the triangle, and accelerate with a BVH
-        Vector3::new(0., 0., 0.)
+        (0..self.triangle_count())
+            .map(|idx| {
+                let triangle = self.triangle(idx);
+                let barycentric = triangle.closest_barycentric(point);
+                let closest = barycentric.x * triangle.point1
+                    + barycentric.y * triangle.point2
+                    + barycentric.z * triangle.point3;
+                ((closest - point).norm_squared(), idx, barycentric)
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map_or_else(Vector3::zeros, |(_, idx, barycentric)| {
+                self.interpolated_normal(idx, &barycentric)
+            })
+    }
+
+    fn bounds(&self) -> AlignedBox3d {
+        self.bvh.bounds()
+    }
+
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let ray = Ray {
+            t_max: ray.t_max.min(t_max),
+            ..*ray
+        };
+        self.bvh
+            .any_hit(&ray, |idx| self.triangle(idx).intersects(&ray).is_some())
+    }
+
+    fn area(&self) -> f64 {
+        self.area_distribution().0
+    }
+
+    // pick a triangle by its area, then a uniform point on it
+    fn sample_surface(&self, u: &Vector2<f64>) -> Option<SurfaceSample> {
+        let (area, triangles) = self.area_distribution();
+        if *area <= 0. {
+            return None;
+        }
+        // the position within the piece is uniform again and can be reused
+        let (x, _, idx) = triangles.sample(u.x);
+        let u_triangle = Vector2::new((x * triangles.len() as f64 - idx as f64).clamp(0., 1.), u.y);
+        self.triangle(idx).sample_surface(&u_triangle)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // write the contents to a file in the temp directory, named after the test so that tests
+    // running in parallel do not share files
+    pub(crate) fn temp_file(name: &str, contents: impl AsRef<[u8]>) -> String {
+        let path = std::env::temp_dir().join(format!("rusty_ray_{}_{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    pub(crate) fn load_error(result: Result<Mesh, MeshLoadError>) -> MeshLoadError {
+        match result {
+            Ok(_) => panic!("the mesh loaded without an error"),
+            Err(error) => error,
+        }
+    }
+
+    // line and kind of an error in a text file
+    pub(crate) fn parse_error(result: Result<Mesh, MeshLoadError>) -> (usize, ParseErrorKind) {
+        match load_error(result) {
+            MeshLoadError::Parse { line, kind, .. } => (line, kind),
+            error => panic!("expected a parse error, got {error}"),
+        }
+    }
+
+    // byte offset and kind of an error in the binary part of a file
+    pub(crate) fn binary_error(result: Result<Mesh, MeshLoadError>) -> (usize, ParseErrorKind) {
+        match load_error(result) {
+            MeshLoadError::Binary { offset, kind, .. } => (offset, kind),
+            error => panic!("expected a binary error, got {error}"),
+        }
+    }
+
+    // a few triangles that first use their vertices in order, so loaders that number vertices
+    // by their first use keep the indices
+    pub(crate) fn round_trip_mesh() -> Mesh {
+        let positions = vec![
+            Vector3::new(0., 0., 0.),
+            Vector3::new(1.5, 0., 0.1),
+            Vector3::new(0.25, 1., -0.3),
+            Vector3::new(0.1, 0.2, 1. / 3.),
+        ];
+        let mut mesh = Mesh::new(positions, vec![[0, 1, 2], [0, 2, 3], [0, 3, 1]]);
+        mesh.uvs = vec![
+            Vector2::new(0., 0.),
+            Vector2::new(1., 0.),
+            Vector2::new(0.5, 1.),
+            Vector2::new(0.1, 0.7),
+        ];
+        mesh
+    }
+
+    // the writers print the shortest representation that parses to the same value, but the
+    // loaders normalize the normals again
+    pub(crate) fn assert_same_geometry(loaded: &Mesh, original: &Mesh) {
+        assert_eq!(loaded.positions, original.positions);
+        assert_eq!(loaded.indices, original.indices);
+        assert_eq!(loaded.normals.len(), original.normals.len());
+        for (loaded, original) in loaded.normals.iter().zip(&original.normals) {
+            assert!((loaded - original).norm() < 1e-15, "{loaded} != {original}");
+        }
+    }
+
+    #[test]
+    fn mesh_normal_comes_from_the_closest_triangle() {
+        // a floor facing up and a wall facing +x that do not share vertices
+        let mesh = Mesh::new(
+            vec![
+                Vector3::new(0., 0., 0.),
+                Vector3::new(0., 0., -1.),
+                Vector3::new(1., 0., 0.),
+                Vector3::new(2., 0., 0.),
+                Vector3::new(2., 1., 0.),
+                Vector3::new(2., 0., -1.),
+            ],
+            vec![[0, 2, 1], [3, 5, 4]],
+        );
+        let normal = |x, y, z| Object::normal(&mesh, &Vector3::new(x, y, z));
+        assert_eq!(normal(0.2, 0., -0.2), Vector3::y());
+        assert_eq!(normal(0.5, 0.1, -0.5), Vector3::y());
+        assert_eq!(normal(2., 0.5, -0.2), Vector3::x());
+        // beyond the edge of the wall
+        assert_eq!(normal(2.5, 3., -0.5), Vector3::x());
+        assert_eq!(normal(-5., -1., 3.), Vector3::y());
+        let empty = Mesh::new(Vec::new(), Vec::new());
+        assert_eq!(Object::normal(&empty, &Vector3::zeros()), Vector3::zeros());
+    }
+
+    fn load_off(name: &str, contents: &str) -> Result<Mesh, MeshLoadError> {
+        Mesh::from_off_file(&temp_file(name, contents))
+    }
+
+    #[test]
+    fn off_polygons_are_split_into_fans() {
+        let mesh = load_off(
+            "off_polygons.off",
+            "OFF\n# a quad and a triangle\n5 2 0\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n\n2 2 2\n\
+             4 0 1 2 3\n3 1 4 2\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions.len(), 5);
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3], [1, 4, 2]]);
+        assert_eq!(mesh.normals.len(), 5);
+    }
+
+    #[test]
+    fn off_counts_on_the_header_line() {
+        let mesh = load_off(
+            "off_counts.off",
+            "OFF 3 1 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.triangle_count(), 1);
+    }
+
+    #[test]
+    fn coff_colors_are_ignored() {
+        let mesh = load_off(
+            "coff.off",
+            "COFF\n3 1 0\n0 0 0 255 0 0 255\n1 0 0 0 255 0 255\n0 1 0 0 0 255 255\n\
+             3 0 1 2 10 20 30\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions[1], Vector3::new(1., 0., 0.));
+        assert_eq!(mesh.indices, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn noff_normals_are_normalized() {
+        let mesh = load_off(
+            "noff.off",
+            "NOFF\n3 1 0\n0 0 0 0 0 2\n1 0 0 0 3 0\n0 1 0 4 0 0\n3 0 1 2\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.normals, vec![Vector3::z(), Vector3::y(), Vector3::x()]);
+    }
+
+    #[test]
+    fn off_bad_header() {
+        let result = load_off("off_bad_header.off", "PLY\n3 1 0\n");
+        assert_eq!(
+            parse_error(result),
+            (1, ParseErrorKind::BadHeader("PLY".to_owned()))
+        );
+    }
+
+    #[test]
+    fn off_short_vertex_list() {
+        let result = load_off("off_short.off", "OFF\n3 1 0\n0 0 0\n1 0 0\n");
+        let error = load_error(result);
+        assert!(error
+            .to_string()
+            .ends_with("end of file: expected 3 vertices but found 2"));
+        let MeshLoadError::Parse { line, kind, .. } = error else {
+            panic!("expected a parse error, got {error}");
+        };
+        assert_eq!(line, 0);
+        assert_eq!(
+            kind,
+            ParseErrorKind::WrongCount {
+                what: "vertices",
+                expected: 3,
+                found: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn off_huge_counts_are_a_short_file() {
+        let result = load_off("off_huge.off", "OFF\n99999999999999 1 0\n0 0 0\n");
+        assert_eq!(
+            parse_error(result),
+            (
+                0,
+                ParseErrorKind::WrongCount {
+                    what: "vertices",
+                    expected: 99999999999999,
+                    found: 1,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn off_index_out_of_range() {
+        let result = load_off(
+            "off_index.off",
+            "OFF\n3 2 0\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n3 0 1 3\n",
+        );
+        assert_eq!(
+            parse_error(result),
+            (7, ParseErrorKind::IndexOutOfRange { index: 3, count: 3 })
+        );
+    }
+
+    #[test]
+    fn off_invalid_number() {
+        let result = load_off(
+            "off_number.off",
+            "OFF\n3 1 0\n0 0 0\n1 x 0\n0 1 0\n3 0 1 2\n",
+        );
+        let error = load_error(result);
+        assert!(error
+            .to_string()
+            .ends_with(":4: \"x\" is not a valid number"));
+    }
+
+    #[test]
+    fn off_face_with_two_corners() {
+        let result = load_off(
+            "off_corners.off",
+            "OFF\n3 1 0\n0 0 0\n1 0 0\n0 1 0\n2 0 1\n",
+        );
+        assert_eq!(
+            parse_error(result),
+            (
+                6,
+                ParseErrorKind::WrongCount {
+                    what: "face corners",
+                    expected: 3,
+                    found: 2,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn off_round_trip() {
+        let mesh = round_trip_mesh();
+        let path = temp_file("off_round_trip.off", "");
+        mesh.save_as_off(&path).unwrap();
+        assert_same_geometry(&Mesh::from_off_file(&path).unwrap(), &mesh);
+    }
+
+    #[test]
+    fn off_missing_file() {
+        let error = load_error(Mesh::from_off_file("does/not/exist.off"));
+        assert!(matches!(error, MeshLoadError::Io { .. }));
     }
 }
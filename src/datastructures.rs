@@ -1,6 +1,7 @@
 use crate::primitives::{Ray, Triangle};
 use nalgebra::Vector3;
 
+#[derive(Debug, Clone, Copy)]
 pub struct AlignedBox3d {
     pub min: Vector3<f64>,
     pub max: Vector3<f64>,
@@ -39,6 +40,61 @@ impl AlignedBox3d {
         self.extend(triangle.point3);
     }
 
+    pub fn extend_box(&mut self, other: &AlignedBox3d) {
+        // extending by the corners would break for empty boxes, whose corners are infinite
+        self.min = self.min.inf(&other.min);
+        self.max = self.max.sup(&other.max);
+    }
+
+    pub fn from_triangle(triangle: &Triangle) -> Self {
+        let mut bbox = Self::default();
+        bbox.extend_triangle(triangle);
+        bbox
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    // corner k is at the max of the box along x, y and z when bit 0, 1 and 2 of k are set
+    pub fn corners(&self) -> [Vector3<f64>; 8] {
+        std::array::from_fn(|k| {
+            Vector3::new(
+                if k & 1 == 0 { self.min.x } else { self.max.x },
+                if k & 2 == 0 { self.min.y } else { self.max.y },
+                if k & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        })
+    }
+
+    pub fn centroid(&self) -> Vector3<f64> {
+        (self.min + self.max) / 2.
+    }
+
+    pub fn diagonal(&self) -> Vector3<f64> {
+        self.max - self.min
+    }
+
+    // index of the axis along which the box is the longest
+    pub fn largest_axis(&self) -> usize {
+        let diag = self.diagonal();
+        if diag.x >= diag.y && diag.x >= diag.z {
+            0
+        } else if diag.y >= diag.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let diag = self.diagonal();
+        2. * (diag.x * diag.y + diag.y * diag.z + diag.z * diag.x)
+    }
+
     pub fn contains(&self, point: Vector3<f64>) -> bool {
         let check_dim = |dim, min, max| point[dim] >= min && point[dim] <= max;
 
@@ -47,108 +103,638 @@ impl AlignedBox3d {
             && check_dim(2, self.min.z, self.max.z)
     }
 
-    pub fn intersects(&self, ray: &Ray) -> bool {
-        let inv_dir = Vector3::new(
-            1.0 / ray.direction.x,
-            1.0 / ray.direction.y,
-            1.0 / ray.direction.z,
-        );
-        let tx1 = (self.min.x - ray.origin.x) * inv_dir.x;
-        let tx2 = (self.max.x - ray.origin.x) * inv_dir.x;
-        let ty1 = (self.min.y - ray.origin.y) * inv_dir.y;
-        let ty2 = (self.max.y - ray.origin.y) * inv_dir.y;
-        let tz1 = (self.min.z - ray.origin.z) * inv_dir.z;
-        let tz2 = (self.max.z - ray.origin.z) * inv_dir.z;
+    // slab test of the ray against the box, restricted to the parameter interval [t_min, t_max]
+    pub fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let inv_dir = ray.direction.map(|d| 1. / d);
+        self.entry_distance(&ray.origin, &inv_dir, t_min, t_max)
+            .is_some()
+    }
 
-        let tmin = tx1.min(tx2).max(ty1.min(ty2)).max(tz1.min(tz2));
-        let tmax = tx1.max(tx2).min(ty1.max(ty2)).min(tz1.max(tz2));
+    // distance along the ray at which it enters the box, if it does so within [t_min, t_max].
+    // takes the inverse direction so that it can be computed once per ray
+    fn entry_distance(
+        &self,
+        origin: &Vector3<f64>,
+        inv_dir: &Vector3<f64>,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<f64> {
+        let tx1 = (self.min.x - origin.x) * inv_dir.x;
+        let tx2 = (self.max.x - origin.x) * inv_dir.x;
+        let ty1 = (self.min.y - origin.y) * inv_dir.y;
+        let ty2 = (self.max.y - origin.y) * inv_dir.y;
+        let tz1 = (self.min.z - origin.z) * inv_dir.z;
+        let tz2 = (self.max.z - origin.z) * inv_dir.z;
 
-        if tmax < 0. || tmin > tmax {
-            return false;
+        let tmin = tx1.min(tx2).max(ty1.min(ty2)).max(tz1.min(tz2)).max(t_min);
+        let tmax = tx1.max(tx2).min(ty1.max(ty2)).min(tz1.max(tz2)).min(t_max);
+
+        if tmin > tmax {
+            return None;
         }
 
-        true
+        Some(tmin)
     }
 }
 
-pub struct AABBNode {
-    pub bbox: AlignedBox3d,
-    pub left: Option<Box<AABBNode>>,
-    pub right: Option<Box<AABBNode>>,
-    pub object_idx: Option<usize>,
+// a node of the flattened bounding volume hierarchy, stored in depth-first order so that
+// the first child of an inner node directly follows it. the bounds are stored in single
+// precision, rounded outwards, to fit the node into 32 bytes
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct LinearNode {
+    min: [f32; 3],
+    max: [f32; 3],
+    // first primitive for leaves, index of the second child for inner nodes
+    offset: u32,
+    // number of primitives, zero for inner nodes
+    primitive_count: u16,
+    // axis the children were split along
+    axis: u8,
+    _pad: u8,
 }
 
-impl AABBNode {
-    pub fn new(
-        bbox: AlignedBox3d,
-        left: Option<Box<AABBNode>>,
-        right: Option<Box<AABBNode>>,
-        object_idx: Option<usize>,
-    ) -> Self {
+const _: () = assert!(std::mem::size_of::<LinearNode>() == 32);
+
+impl LinearNode {
+    fn new(bbox: &AlignedBox3d, offset: usize, primitive_count: usize, axis: usize) -> Self {
+        let round_down = |x: f64| {
+            let y = x as f32;
+            if y as f64 > x {
+                y.next_down()
+            } else {
+                y
+            }
+        };
+        let round_up = |x: f64| {
+            let y = x as f32;
+            if (y as f64) < x {
+                y.next_up()
+            } else {
+                y
+            }
+        };
+
         Self {
-            bbox,
-            left,
-            right,
-            object_idx,
+            min: [
+                round_down(bbox.min.x),
+                round_down(bbox.min.y),
+                round_down(bbox.min.z),
+            ],
+            max: [
+                round_up(bbox.max.x),
+                round_up(bbox.max.y),
+                round_up(bbox.max.z),
+            ],
+            offset: offset as u32,
+            primitive_count: primitive_count as u16,
+            axis: axis as u8,
+            _pad: 0,
+        }
+    }
+
+    pub fn bbox(&self) -> AlignedBox3d {
+        AlignedBox3d::new(
+            Vector3::new(self.min[0] as f64, self.min[1] as f64, self.min[2] as f64),
+            Vector3::new(self.max[0] as f64, self.max[1] as f64, self.max[2] as f64),
+        )
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.primitive_count > 0
+    }
+
+    // range into the primitive indices of the bvh, empty for inner nodes
+    pub fn primitives(&self) -> std::ops::Range<usize> {
+        let first = self.offset as usize;
+        first..first + self.primitive_count as usize
+    }
+
+    pub fn second_child(&self) -> Option<usize> {
+        if self.is_leaf() {
+            None
+        } else {
+            Some(self.offset as usize)
         }
     }
 }
 
-pub struct BVH {
-    pub root: AABBNode,
+#[derive(Debug, Clone, Copy)]
+pub enum SplitStrategy {
+    // split at the median centroid along the largest axis of the centroid bounds
+    Median,
+    // evaluate the surface area heuristic at the boundaries of equally sized bins on every axis
+    Sah { bins: usize },
+}
 
+#[derive(Debug, Clone, Copy)]
+pub struct BvhBuildOptions {
+    pub strategy: SplitStrategy,
+    // nodes with at most this many primitives may become leaves
+    pub max_leaf_size: usize,
+    // cost of traversing an inner node relative to intersecting a primitive, used by the sah
+    pub traversal_cost: f64,
 }
 
-impl BVH {
-    pub fn new(triangles: &Vec<Triangle>) -> Self {
-        let triangle_indices: Vec<usize> = (0..triangles.len()).collect();
+impl Default for BvhBuildOptions {
+    fn default() -> Self {
         Self {
-            root: Self::create_node(&triangles, triangle_indices),
+            strategy: SplitStrategy::Sah { bins: 12 },
+            max_leaf_size: 4,
+            traversal_cost: 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub depth: usize,
+    // expected cost of a random ray, in units of primitive intersections
+    pub sah_cost: f64,
+}
+
+pub struct BVH {
+    pub nodes: Vec<LinearNode>,
+    // primitive indices reordered so that every leaf covers a contiguous range
+    pub primitive_indices: Vec<usize>,
+    pub stats: BvhStats,
+}
+
+impl BVH {
+    pub fn new(triangles: &[Triangle]) -> Self {
+        Self::from_triangles(triangles, &BvhBuildOptions::default())
+    }
+
+    pub fn from_triangles(triangles: &[Triangle], options: &BvhBuildOptions) -> Self {
+        let bounds: Vec<AlignedBox3d> = triangles.iter().map(AlignedBox3d::from_triangle).collect();
+        Self::build(&bounds, options)
+    }
+
+    // build the hierarchy over arbitrary primitives given by their bounding boxes
+    pub fn build(bounds: &[AlignedBox3d], options: &BvhBuildOptions) -> Self {
+        let centroids: Vec<Vector3<f64>> = bounds.iter().map(|bbox| bbox.centroid()).collect();
+        let mut primitive_indices: Vec<usize> = (0..bounds.len()).collect();
+        let mut builder = Builder {
+            bounds,
+            centroids: &centroids,
+            options,
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            stats: BvhStats::default(),
+        };
+        if !bounds.is_empty() {
+            builder.create_node(&mut primitive_indices, 0, 1);
+        }
+
+        let mut bvh = Self {
+            nodes: builder.nodes,
+            primitive_indices,
+            stats: builder.stats,
+        };
+        bvh.stats.sah_cost = bvh.sah_cost(options);
+        bvh
+    }
+
+    pub fn bounds(&self) -> AlignedBox3d {
+        self.nodes
+            .first()
+            .map_or(AlignedBox3d::default(), |root| root.bbox())
+    }
+
+    // boxes of the nodes down to the given depth, the root is at depth zero. leaves above that
+    // depth are included as well
+    pub fn node_boxes(&self, max_depth: usize) -> Vec<AlignedBox3d> {
+        let mut boxes = Vec::new();
+        if self.nodes.is_empty() {
+            return boxes;
         }
+        let mut stack = vec![(0, 0)];
+        while let Some((idx, depth)) = stack.pop() {
+            let node = &self.nodes[idx];
+            boxes.push(node.bbox());
+            if depth < max_depth {
+                if let Some(second) = node.second_child() {
+                    stack.push((second, depth + 1));
+                    stack.push((idx + 1, depth + 1));
+                }
+            }
+        }
+        boxes
+    }
+
+    fn sah_cost(&self, options: &BvhBuildOptions) -> f64 {
+        let root_area = self.bounds().surface_area();
+        if root_area <= 0. {
+            return 0.;
+        }
+        self.nodes
+            .iter()
+            .map(|node| {
+                let area = node.bbox().surface_area() / root_area;
+                if node.is_leaf() {
+                    area * node.primitive_count as f64
+                } else {
+                    area * options.traversal_cost
+                }
+            })
+            .sum()
     }
 
-    fn create_node(triangles: &[Triangle], triangle_indices: Vec<usize>) -> AABBNode {
-        if triangle_indices.is_empty() {
-            panic!("Cannot create an AABBNode with no triangles.");
+    // find the closest primitive along the ray. intersect is called with a primitive index and
+    // the ray with its interval shortened to the closest hit so far, and returns the distance and
+    // data of a hit within that interval. children are visited front to back and boxes behind the
+    // closest hit are skipped
+    pub fn closest_hit<T>(
+        &self,
+        ray: &Ray,
+        mut intersect: impl FnMut(usize, &Ray) -> Option<(f64, T)>,
+    ) -> Option<T> {
+        if self.nodes.is_empty() {
+            return None;
         }
 
-        // if leaf node
-        if triangle_indices.len() == 1 {
-            let mut bbox = AlignedBox3d::default();
-            bbox.extend_triangle(&triangles[triangle_indices[0]]);
-            return AABBNode::new(bbox, None, None, Some(triangle_indices[0]));
+        let inv_dir = ray.direction.map(|d| 1. / d);
+        let mut closest = None;
+        let mut ray = *ray;
+        let mut stack = Vec::with_capacity(64);
+        let mut node_idx = 0;
+
+        loop {
+            let node = &self.nodes[node_idx];
+            if node
+                .bbox()
+                .entry_distance(&ray.origin, &inv_dir, ray.t_min, ray.t_max)
+                .is_some()
+            {
+                if node.is_leaf() {
+                    for idx in node.primitives() {
+                        if let Some((t, hit)) = intersect(self.primitive_indices[idx], &ray) {
+                            ray.t_max = t;
+                            closest = Some(hit);
+                        }
+                    }
+                } else {
+                    // visit the child on the near side of the split first
+                    let first = node_idx + 1;
+                    let second = node.offset as usize;
+                    if ray.direction[node.axis as usize] < 0. {
+                        stack.push(first);
+                        node_idx = second;
+                    } else {
+                        stack.push(second);
+                        node_idx = first;
+                    }
+                    continue;
+                }
+            }
+
+            match stack.pop() {
+                Some(next) => node_idx = next,
+                None => break,
+            }
         }
 
+        closest
+    }
+
+    // check if any primitive is hit within the interval of the ray, stopping at the first one
+    // found. intersect is called with a primitive index and returns whether it blocks the ray
+    pub fn any_hit(&self, ray: &Ray, mut intersect: impl FnMut(usize) -> bool) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_dir = ray.direction.map(|d| 1. / d);
+        let mut stack = Vec::with_capacity(64);
+        let mut node_idx = 0;
+
+        loop {
+            let node = &self.nodes[node_idx];
+            if node
+                .bbox()
+                .entry_distance(&ray.origin, &inv_dir, ray.t_min, ray.t_max)
+                .is_some()
+            {
+                if node.is_leaf() {
+                    for idx in node.primitives() {
+                        if intersect(self.primitive_indices[idx]) {
+                            return true;
+                        }
+                    }
+                } else {
+                    // any order works, the first child is the cheapest to reach
+                    stack.push(node.offset as usize);
+                    node_idx += 1;
+                    continue;
+                }
+            }
+
+            match stack.pop() {
+                Some(next) => node_idx = next,
+                None => return false,
+            }
+        }
+    }
+}
+
+struct Builder<'a> {
+    bounds: &'a [AlignedBox3d],
+    centroids: &'a [Vector3<f64>],
+    options: &'a BvhBuildOptions,
+    nodes: Vec<LinearNode>,
+    stats: BvhStats,
+}
+
+impl Builder<'_> {
+    // build the subtree over indices, which start at offset in the full index list,
+    // and return the position of its root in the node list
+    fn create_node(&mut self, indices: &mut [usize], offset: usize, depth: usize) -> usize {
+        self.stats.node_count += 1;
+        self.stats.depth = self.stats.depth.max(depth);
+
         let mut bbox = AlignedBox3d::default();
-        for idx in &triangle_indices {
-            bbox.extend_triangle(&triangles[*idx]);
+        let mut centroid_bounds = AlignedBox3d::default();
+        for idx in indices.iter() {
+            bbox.extend_box(&self.bounds[*idx]);
+            centroid_bounds.extend(self.centroids[*idx]);
         }
 
+        let node_idx = self.nodes.len();
+        let (axis, mid) = match self.split(indices, &bbox, &centroid_bounds) {
+            Some(split) => split,
+            None => {
+                self.stats.leaf_count += 1;
+                self.nodes
+                    .push(LinearNode::new(&bbox, offset, indices.len(), 0));
+                return node_idx;
+            }
+        };
 
-        // if not leaf node
-        let diag = bbox.max - bbox.min;
-        let axis_index = diag.x.max(diag.y.max(diag.z)) as usize;
+        // the offset of the second child is only known once the first subtree is built
+        self.nodes.push(LinearNode::new(&bbox, 0, 0, axis));
+        let (left_indices, right_indices) = indices.split_at_mut(mid);
+        self.create_node(left_indices, offset, depth + 1);
+        let second_child = self.create_node(right_indices, offset + mid, depth + 1);
+        self.nodes[node_idx].offset = second_child as u32;
 
-        let mut sorted_indices = triangle_indices;
-        // sorted_indices.sort_by(|a, b| {
-        //     let a_center = triangles[*a].centroid();
-        //     let b_center = triangles[*b].centroid();
+        node_idx
+    }
 
-        //     a_center[axis_index]
-        //         .partial_cmp(&b_center[axis_index])
-        //         .unwrap_or(Ordering::Equal)
-        // });
+    fn max_leaf_size(&self) -> usize {
+        self.options.max_leaf_size.clamp(1, u16::MAX as usize)
+    }
 
-        let mid = sorted_indices.len() / 2;
-        let (left_triangle_indices, right_triangle_indices) = sorted_indices.split_at(mid);
+    // partition the indices in place and return the split axis and the size of the left part,
+    // or None if the node should become a leaf
+    fn split(
+        &self,
+        indices: &mut [usize],
+        bbox: &AlignedBox3d,
+        centroid_bounds: &AlignedBox3d,
+    ) -> Option<(usize, usize)> {
+        let count = indices.len();
+        if count <= 1 {
+            return None;
+        }
 
-        let left = Self::create_node(triangles, left_triangle_indices.to_vec());
-        let right = Self::create_node(triangles, right_triangle_indices.to_vec());
+        let axis = centroid_bounds.largest_axis();
+        if centroid_bounds.diagonal()[axis] <= 0. {
+            // all centroids coincide, no plane can separate them
+            return if count <= self.max_leaf_size() {
+                None
+            } else {
+                Some((axis, count / 2))
+            };
+        }
 
-        AABBNode::new(bbox, Some(Box::new(left)), Some(Box::new(right)), None)
+        match self.options.strategy {
+            SplitStrategy::Median => {
+                if count <= self.max_leaf_size() {
+                    return None;
+                }
+                let mid = count / 2;
+                indices.select_nth_unstable_by(mid, |a, b| {
+                    self.centroids[*a][axis].total_cmp(&self.centroids[*b][axis])
+                });
+                Some((axis, mid))
+            }
+            SplitStrategy::Sah { bins } => {
+                self.sah_split(indices, bbox, centroid_bounds, bins.max(2))
+            }
+        }
     }
 
-    
+    fn sah_split(
+        &self,
+        indices: &mut [usize],
+        bbox: &AlignedBox3d,
+        centroid_bounds: &AlignedBox3d,
+        bin_count: usize,
+    ) -> Option<(usize, usize)> {
+        let count = indices.len();
+        let bin_of = |idx: usize, axis: usize| {
+            let extent = centroid_bounds.diagonal()[axis];
+            let offset = (self.centroids[idx][axis] - centroid_bounds.min[axis]) / extent;
+            ((offset * bin_count as f64) as usize).min(bin_count - 1)
+        };
+
+        // (cost, axis, number of bins on the left)
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            if centroid_bounds.diagonal()[axis] <= 0. {
+                continue;
+            }
+
+            let mut bin_bounds = vec![AlignedBox3d::default(); bin_count];
+            let mut bin_counts = vec![0; bin_count];
+            for idx in indices.iter() {
+                let bin = bin_of(*idx, axis);
+                bin_bounds[bin].extend_box(&self.bounds[*idx]);
+                bin_counts[bin] += 1;
+            }
+
+            // sweep from the right to get the area and count of every right part
+            let mut right_areas = vec![0.; bin_count];
+            let mut right_counts = vec![0; bin_count];
+            let mut right_bbox = AlignedBox3d::default();
+            let mut right_count = 0;
+            for bin in (1..bin_count).rev() {
+                right_bbox.extend_box(&bin_bounds[bin]);
+                right_count += bin_counts[bin];
+                right_areas[bin] = right_bbox.surface_area();
+                right_counts[bin] = right_count;
+            }
+
+            let mut left_bbox = AlignedBox3d::default();
+            let mut left_count = 0;
+            for split in 1..bin_count {
+                left_bbox.extend_box(&bin_bounds[split - 1]);
+                left_count += bin_counts[split - 1];
+                if left_count == 0 || right_counts[split] == 0 {
+                    continue;
+                }
+                let cost = left_bbox.surface_area() * left_count as f64
+                    + right_areas[split] * right_counts[split] as f64;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let area = bbox.surface_area().max(f64::MIN_POSITIVE);
+        let leaf_cost = count as f64;
+        let (split_cost, axis, split) = match best {
+            Some((cost, axis, split)) => (self.options.traversal_cost + cost / area, axis, split),
+            None => return None,
+        };
+        if count <= self.max_leaf_size() && leaf_cost <= split_cost {
+            return None;
+        }
+
+        // partition in place, primitives in bins left of the split go first
+        let mut mid = 0;
+        for k in 0..count {
+            if bin_of(indices[k], axis) < split {
+                indices.swap(k, mid);
+                mid += 1;
+            }
+        }
+        Some((axis, mid))
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::Object;
+    use crate::sampling::Rng;
+
+    fn random_point(rng: &mut Rng, extent: f64) -> Vector3<f64> {
+        Vector3::new(rng.next_f64(), rng.next_f64(), rng.next_f64()).map(|x| (2. * x - 1.) * extent)
+    }
+
+    // small triangles scattered through a cube, so rays hit several of them
+    fn random_triangles(rng: &mut Rng, count: usize) -> Vec<Triangle> {
+        (0..count)
+            .map(|_| {
+                let center = random_point(rng, 1.);
+                Triangle::new(
+                    center + random_point(rng, 0.3),
+                    center + random_point(rng, 0.3),
+                    center + random_point(rng, 0.3),
+                )
+            })
+            .collect()
+    }
+
+    // from outside or inside the cube towards a point in it, so most rays pass the triangles
+    fn random_ray(rng: &mut Rng, t_max: f64) -> Ray {
+        let origin = random_point(rng, 2.);
+        let direction = (random_point(rng, 1.) - origin).normalize();
+        Ray::with_interval(origin, direction, 0., t_max)
+    }
+
+    fn strategies() -> [BvhBuildOptions; 3] {
+        [
+            BvhBuildOptions::default(),
+            BvhBuildOptions {
+                strategy: SplitStrategy::Median,
+                ..BvhBuildOptions::default()
+            },
+            BvhBuildOptions {
+                max_leaf_size: 1,
+                ..BvhBuildOptions::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn closest_hit_matches_brute_force() {
+        let mut rng = Rng::new(1);
+        let triangles = random_triangles(&mut rng, 150);
+        for options in strategies() {
+            let bvh = BVH::from_triangles(&triangles, &options);
+            let mut hits = 0;
+            for _ in 0..400 {
+                let ray = random_ray(&mut rng, f64::INFINITY);
+                let expected = triangles
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, triangle)| Some((triangle.intersects(&ray)?.t, idx)))
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+                let found = bvh.closest_hit(&ray, |idx, ray| {
+                    triangles[idx]
+                        .intersects(ray)
+                        .map(|hit_point| (hit_point.t, (hit_point.t, idx)))
+                });
+                assert_eq!(found, expected, "{options:?}");
+                hits += found.is_some() as usize;
+            }
+            assert!(hits > 100, "too few rays hit anything to compare");
+        }
+    }
+
+    #[test]
+    fn any_hit_matches_brute_force() {
+        let mut rng = Rng::new(2);
+        let triangles = random_triangles(&mut rng, 150);
+        for options in strategies() {
+            let bvh = BVH::from_triangles(&triangles, &options);
+            let mut blocked = 0;
+            for _ in 0..400 {
+                let t_max = 4. * rng.next_f64();
+                let ray = random_ray(&mut rng, t_max);
+                let expected = triangles
+                    .iter()
+                    .any(|triangle| triangle.intersects(&ray).is_some());
+                let found = bvh.any_hit(&ray, |idx| triangles[idx].intersects(&ray).is_some());
+                assert_eq!(found, expected, "{options:?}");
+                blocked += found as usize;
+            }
+            assert!(
+                blocked > 40 && blocked < 360,
+                "the rays should not all agree"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_bvh() {
+        let bvh = BVH::new(&[]);
+        let ray = Ray::new(Vector3::zeros(), Vector3::x());
+        assert!(bvh.closest_hit(&ray, |_, _| Some((1., ()))).is_none());
+        assert!(!bvh.any_hit(&ray, |_| true));
+        assert!(bvh.bounds().is_empty());
+        assert!(bvh.node_boxes(3).is_empty());
+    }
+
+    #[test]
+    fn single_primitive() {
+        let triangle = Triangle::new(
+            Vector3::new(1., -1., -1.),
+            Vector3::new(1., 1., -1.),
+            Vector3::new(1., 0., 1.),
+        );
+        let bvh = BVH::new(&[triangle]);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert!(bvh.nodes[0].is_leaf());
+
+        let intersect = |idx: usize, ray: &Ray| {
+            assert_eq!(idx, 0);
+            triangle
+                .intersects(ray)
+                .map(|hit_point| (hit_point.t, hit_point.t))
+        };
+        let towards = Ray::new(Vector3::zeros(), Vector3::x());
+        assert_eq!(bvh.closest_hit(&towards, intersect), Some(1.));
+        assert!(bvh.any_hit(&towards, |_| triangle.intersects(&towards).is_some()));
 
+        let away = Ray::new(Vector3::zeros(), -Vector3::x());
+        assert_eq!(bvh.closest_hit(&away, intersect), None);
+        assert!(!bvh.any_hit(&away, |_| panic!("the box is behind the ray")));
+
+        let short = Ray::with_interval(Vector3::zeros(), Vector3::x(), 0., 0.5);
+        assert_eq!(bvh.closest_hit(&short, intersect), None);
+    }
+}
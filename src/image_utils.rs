@@ -1,8 +1,8 @@
-use crate::primitives::Camera;
+use crate::render::Framebuffer;
 
-pub fn save_as_png(camera: Camera, filename: &str) {
-    let width = camera.image.nrows();
-    let height = camera.image.ncols();
+pub fn save_as_png(image: &Framebuffer, filename: &str) {
+    let width = image.nrows();
+    let height = image.ncols();
     // convert to u8 for all channels and imgbuf to save as png
     let mut imgbuf = image::ImageBuffer::new(width as u32, height as u32);
     for (x, y, pixel) in imgbuf.enumerate_pixels_mut() {
@@ -12,7 +12,7 @@ pub fn save_as_png(camera: Camera, filename: &str) {
         // invert y axis
         // let j = height - y as usize - 1;
         
-        let color = camera.image[(i, j)].map(|c| (c * 255.) as u8);
+        let color = image[(i, j)].map(|c| (c * 255.) as u8);
         let color_array: [u8; 4] = color.as_slice().try_into().unwrap();
         *pixel = image::Rgba(color_array);
     }
@@ -1,7 +1,15 @@
+pub mod background;
 pub mod composites;
 pub mod datastructures;
-pub mod primitives;
 pub mod image_utils;
-pub mod utils;
+pub mod lights;
+pub mod obj;
+pub mod pathtracer;
+pub mod ply;
+pub mod primitives;
+pub mod raster;
+pub mod render;
+pub mod sampling;
+pub mod stl;
 pub mod textures;
-pub mod raster;
\ No newline at end of file
+pub mod utils;
@@ -0,0 +1,220 @@
+use crate::primitives::{offset_ray_origin, Scene};
+use nalgebra::{Vector2, Vector3};
+
+// how the light of point and spot lights falls off with the distance d. the reference radius
+// is the distance at which the light has its nominal color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attenuation {
+    None,
+    // radius / d
+    Linear { radius: f64 },
+    // (radius / d)^2, physically correct
+    InverseSquare { radius: f64 },
+}
+
+impl Attenuation {
+    pub fn factor(&self, distance: f64) -> f64 {
+        match *self {
+            Attenuation::None => 1.,
+            Attenuation::Linear { radius } => radius / distance,
+            Attenuation::InverseSquare { radius } => (radius / distance).powi(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Light {
+    // infinitely small light, color is its intensity
+    Point {
+        position: Vector3<f64>,
+        color: Vector3<f64>,
+        attenuation: Attenuation,
+    },
+    // light from infinitely far away like the sun, arriving everywhere from the same direction
+    Directional {
+        // direction the light travels in
+        direction: Vector3<f64>,
+        color: Vector3<f64>,
+    },
+    // point light restricted to a cone around the direction. the light fades out smoothly over
+    // the outermost penumbra radians of the cone_angle, both measured from the axis
+    Spot {
+        position: Vector3<f64>,
+        direction: Vector3<f64>,
+        color: Vector3<f64>,
+        cone_angle: f64,
+        penumbra: f64,
+        attenuation: Attenuation,
+    },
+    // the scene object with the given index, whose material is emissive. Scene::new creates
+    // one for every emissive object that supports surface sampling
+    Area {
+        object: usize,
+    },
+}
+
+// incident light at a point from one position on a light
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    // where shadow rays have to reach, already moved off the surface of area lights. None for
+    // directional lights, whose shadow rays never end
+    pub position: Option<Vector3<f64>>,
+    // unit vector from the shaded point towards the light
+    pub direction: Vector3<f64>,
+    pub radiance: Vector3<f64>,
+    // density of the direction in solid angle, 1 for delta lights
+    pub pdf: f64,
+    // all but area lights can only be reached by sampling them
+    pub delta: bool,
+}
+
+impl Light {
+    // falls off with the inverse square of the distance
+    pub fn point(position: Vector3<f64>, color: Vector3<f64>) -> Self {
+        Light::Point {
+            position,
+            color,
+            attenuation: Attenuation::InverseSquare { radius: 1. },
+        }
+    }
+
+    pub fn directional(direction: Vector3<f64>, color: Vector3<f64>) -> Self {
+        Light::Directional {
+            direction: direction.normalize(),
+            color,
+        }
+    }
+
+    pub fn spot(
+        position: Vector3<f64>,
+        direction: Vector3<f64>,
+        color: Vector3<f64>,
+        cone_angle: f64,
+        penumbra: f64,
+    ) -> Self {
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            color,
+            cone_angle,
+            penumbra: penumbra.clamp(0., cone_angle),
+            attenuation: Attenuation::InverseSquare { radius: 1. },
+        }
+    }
+
+    // only point and spot lights are attenuated, other lights are returned unchanged
+    pub fn with_attenuation(mut self, new_attenuation: Attenuation) -> Self {
+        match &mut self {
+            Light::Point { attenuation, .. } | Light::Spot { attenuation, .. } => {
+                *attenuation = new_attenuation
+            }
+            Light::Directional { .. } | Light::Area { .. } => {}
+        }
+        self
+    }
+
+    pub fn is_delta(&self) -> bool {
+        !matches!(self, Light::Area { .. })
+    }
+
+    // direction towards the light and the light arriving at the point for all lights but area
+    // lights, which need to be sampled. shadows are not taken into account
+    pub fn incident(&self, point: &Vector3<f64>) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        match self {
+            Light::Point {
+                position,
+                color,
+                attenuation,
+            } => {
+                let to_light = position - point;
+                let distance = to_light.norm();
+                Some((to_light / distance, color * attenuation.factor(distance)))
+            }
+            Light::Directional { direction, color } => Some((-direction, *color)),
+            Light::Spot {
+                position,
+                direction,
+                color,
+                cone_angle,
+                penumbra,
+                attenuation,
+            } => {
+                let to_light = position - point;
+                let distance = to_light.norm();
+                let to_light = to_light / distance;
+                let cos_theta = -to_light.dot(direction);
+                let (cos_outer, cos_inner) = (cone_angle.cos(), (cone_angle - penumbra).cos());
+                let falloff = if cos_theta >= cos_inner {
+                    1.
+                } else {
+                    smoothstep(cos_outer, cos_inner, cos_theta)
+                };
+                Some((to_light, color * falloff * attenuation.factor(distance)))
+            }
+            Light::Area { .. } => None,
+        }
+    }
+
+    // pick a point on the light as seen from the given point, None if the sampled point
+    // does not emit towards it
+    pub fn sample(
+        &self,
+        scene: &Scene,
+        point: &Vector3<f64>,
+        u: &Vector2<f64>,
+    ) -> Option<LightSample> {
+        match self {
+            Light::Point { position, .. } | Light::Spot { position, .. } => {
+                let (direction, radiance) = self.incident(point)?;
+                Some(LightSample {
+                    position: Some(*position),
+                    direction,
+                    radiance,
+                    pdf: 1.,
+                    delta: true,
+                })
+            }
+            Light::Directional { .. } => {
+                let (direction, radiance) = self.incident(point)?;
+                Some(LightSample {
+                    position: None,
+                    direction,
+                    radiance,
+                    pdf: 1.,
+                    delta: true,
+                })
+            }
+            Light::Area { object } => {
+                let object = &scene.objects[*object];
+                let surface = object.shape.sample_surface(u)?;
+                let to_light = surface.point - point;
+                let distance_squared = to_light.norm_squared();
+                let direction = to_light / distance_squared.sqrt();
+                // emitters only shine from their front side
+                let cos_light = -surface.normal.dot(&direction);
+                if cos_light <= 0. {
+                    return None;
+                }
+                Some(LightSample {
+                    position: Some(offset_ray_origin(
+                        &surface.point,
+                        &surface.error,
+                        &surface.normal,
+                        &-direction,
+                    )),
+                    direction,
+                    radiance: scene.material(object.material).emission,
+                    // convert the uniform density over the area to solid angle
+                    pdf: distance_squared / (cos_light * object.shape.area()),
+                    delta: false,
+                })
+            }
+        }
+    }
+}
+
+// hermite interpolation from 0 at edge0 to 1 at edge1
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
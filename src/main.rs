@@ -1,110 +1,179 @@
-use kdam::tqdm;
-use nalgebra::Vector3;
+use nalgebra::{Vector3, Vector4};
 use rusty_ray::{
-    composites, image_utils, primitives,
+    composites, image_utils, lights, primitives,
     raster::{self, Program, Uniform},
-    utils,
+    render::{self, Framebuffer, RenderSettings},
 };
+use std::f64::consts::FRAC_PI_4;
+use std::path::Path;
+
+// picks the loader by the extension, problems that do not stop the load are printed
+fn load_mesh(path: &str) -> Option<composites::Mesh> {
+    let result = match Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some("obj") => composites::Mesh::from_obj_file(path).map(|(mesh, warnings)| {
+            for warning in warnings {
+                eprintln!("warning: {warning}");
+            }
+            mesh
+        }),
+        Some("ply") => composites::Mesh::from_ply_file(path),
+        Some("stl") => composites::Mesh::from_stl_file(path),
+        _ => composites::Mesh::from_off_file(path),
+    };
+    match result {
+        Ok(mesh) => Some(mesh),
+        Err(error) => {
+            eprintln!("could not load the mesh: {error}");
+            None
+        }
+    }
+}
 
 fn raytracing_task() {
-    // set the objects
-    let objects: Vec<Box<dyn primitives::Object>> = vec![
-        Box::new(primitives::Sphere::new(Vector3::new(10., 0., 1.), 1.)),
-        Box::new(primitives::Sphere::new(Vector3::new(7., 0.05, -1.), 1.)),
-        Box::new(primitives::Sphere::new(Vector3::new(4., 0.1, 1.), 1.)),
-        Box::new(primitives::Sphere::new(Vector3::new(1., 0.2, -1.), 1.)),
-        Box::new(primitives::Sphere::new(Vector3::new(-2., 0.4, 1.), 1.)),
-        Box::new(primitives::Sphere::new(Vector3::new(-5., 0.8, -1.), 1.)),
-        Box::new(primitives::Sphere::new(Vector3::new(-8., 1.6, 1.), 1.)),
-        Box::new(primitives::Parallelogram::new(
-            Vector3::new(-100., -1.25, -100.),
-            Vector3::new(100., 0., -100.),
-            Vector3::new(-100., -1.2, 100.),
-        )),
+    // set the materials, objects refer to them by their index
+    let materials = vec![
+        // grey
+        primitives::Material::new(
+            Vector3::new(0.5, 0.5, 0.5),
+            Vector3::new(0.2, 0.2, 0.2),
+            256.,
+            Vector3::new(0.7, 0.7, 0.7),
+            Vector3::new(0., 0., 0.),
+        ),
+        // red
+        primitives::Material::new(
+            Vector3::new(0.8, 0.1, 0.1),
+            Vector3::new(0.2, 0.2, 0.2),
+            256.,
+            Vector3::new(0.1, 0.1, 0.1),
+            Vector3::new(0., 0., 0.),
+        ),
+        // glass
+        primitives::Material::new(
+            Vector3::new(0., 0., 0.),
+            Vector3::new(0.2, 0.2, 0.2),
+            256.,
+            Vector3::new(1., 1., 1.),
+            Vector3::new(1., 1., 1.),
+        )
+        .with_refractive_index(1.5),
     ];
+    let (grey, red, glass) = (0, 1, 2);
 
-    // set the materials
-    let material = primitives::Material::new(
-        Vector3::new(0.5, 0.5, 0.5),
-        Vector3::new(0.2, 0.2, 0.2),
-        256.,
-        Vector3::new(0.7, 0.7, 0.7),
-        Vector3::new(0.7, 0.7, 0.7),
-    );
+    // set the objects
+    let objects = vec![
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(10., 0., 1.), 1.)),
+            grey,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(7., 0.05, -1.), 1.)),
+            grey,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(4., 0.1, 1.), 1.)),
+            glass,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(1., 0.2, -1.), 1.)),
+            red,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(-2., 0.4, 1.), 1.)),
+            grey,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(-5., 0.8, -1.), 1.)),
+            grey,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Sphere::new(Vector3::new(-8., 1.6, 1.), 1.)),
+            grey,
+        ),
+        primitives::SceneObject::new(
+            Box::new(primitives::Parallelogram::new(
+                Vector3::new(-100., -1.25, -100.),
+                Vector3::new(100., 0., -100.),
+                Vector3::new(-100., -1.2, 100.),
+            )),
+            grey,
+        ),
+    ];
 
     // set the lights
-    let lights: Vec<primitives::Light> = vec![
-        primitives::Light::new(Vector3::new(8., 8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(6., -8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(4., 8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(2., -8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(0., 8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(-2., -8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(-4., 8., 0.), Vector3::new(16., 16., 16.)),
+    let lights: Vec<lights::Light> = vec![
+        lights::Light::point(Vector3::new(8., 8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(6., -8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(4., 8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(2., -8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(0., 8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(-2., -8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(-4., 8., 0.), Vector3::new(16., 16., 16.)),
     ];
 
     // set the scene
     let ambient_light = Vector3::new(0.2, 0.2, 0.2);
     let ambient_color = Vector3::new(0.5, 0.1, 0.1);
     let ambient_color = ambient_color.component_mul(&ambient_light);
-    let scene = primitives::Scene::new(objects, lights, ambient_color);
+    let scene = primitives::Scene::new(objects, materials, lights, ambient_color);
 
     // set the camera
-    let mut camera = primitives::Camera::new(
-        0.7854,
+    let camera = primitives::Camera::look_at(
+        FRAC_PI_4,
         10.,
         1200,
         800,
         Vector3::new(0., 1., 10.),
+        Vector3::new(0., 0., 0.),
+        Vector3::new(0., 1., 0.),
         primitives::CameraKind::PERSPECTIVE,
     );
 
     // render
-    for i in 0..camera.width {
-        for j in 0..camera.height {
-            let ray = camera.ray(i, j);
-            let color = utils::shoot_ray(&ray, &scene, &material, 5);
-            camera.image[(i, j)] = color;
-        }
-    }
+    let image = render::render(&scene, &camera, &RenderSettings::new(5));
 
-    image_utils::save_as_png(camera, "raytracing.png");
+    image_utils::save_as_png(&image, "raytracing.png");
 }
 
 fn bvh_task() {
-    // set the objects
-    let objects: Vec<Box<dyn primitives::Object>> =
-        // vec![Box::new(composites::Mesh::from_off_file("data/bunny.off"))];
-    vec![Box::new(composites::Mesh::from_off_file("data/dragon.off"))];
-
     // set the materials
-    let material = primitives::Material::new(
+    let materials = vec![primitives::Material::new(
         Vector3::new(0.5, 0.5, 0.5),
         Vector3::new(0.2, 0.2, 0.2),
         256.,
         Vector3::new(0.7, 0.7, 0.7),
-        Vector3::new(0.7, 0.7, 0.7),
-    );
+        Vector3::new(0., 0., 0.),
+    )];
+
+    // set the objects
+    // let Some(mesh) = load_mesh("data/bunny.off") else {
+    let Some(mesh) = load_mesh("data/dragon.off") else {
+        return;
+    };
+    let objects = vec![primitives::SceneObject::new(Box::new(mesh), 0)];
 
     // set the lights
-    let lights: Vec<primitives::Light> = vec![
-        primitives::Light::new(Vector3::new(8., 8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(6., -8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(4., 8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(2., -8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(0., 8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(-2., -8., 0.), Vector3::new(16., 16., 16.)),
-        primitives::Light::new(Vector3::new(-4., 8., 0.), Vector3::new(16., 16., 16.)),
+    let lights: Vec<lights::Light> = vec![
+        lights::Light::point(Vector3::new(8., 8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(6., -8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(4., 8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(2., -8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(0., 8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(-2., -8., 0.), Vector3::new(16., 16., 16.)),
+        lights::Light::point(Vector3::new(-4., 8., 0.), Vector3::new(16., 16., 16.)),
     ];
 
     // set the scene
     let ambient_light = Vector3::new(0.2, 0.2, 0.2);
     let ambient_color = Vector3::new(0.5, 0.1, 0.1);
     let ambient_color = ambient_color.component_mul(&ambient_light);
-    let scene = primitives::Scene::new(objects, lights, ambient_color);
+    let scene = primitives::Scene::new(objects, materials, lights, ambient_color);
 
     // set the camera
-    let mut camera = primitives::Camera::new(
+    let camera = primitives::Camera::new(
         0.3491,
         5.,
         640,
@@ -114,45 +183,34 @@ fn bvh_task() {
     );
 
     // render
-    for i in 0..camera.width {
-        for j in 0..camera.height {
-            let ray = camera.ray(i, j);
-            let color = utils::shoot_ray(&ray, &scene, &material, 5);
-            camera.image[(i, j)] = color;
-        }
-    }
+    let image = render::render(&scene, &camera, &RenderSettings::new(5));
 
-    image_utils::save_as_png(camera, "bvh.png");
+    image_utils::save_as_png(&image, "bvh.png");
 }
 
 fn raster_task() {
-    // let mesh = composites::Mesh::from_off_file("data/bunny.off");
-    let mesh = composites::Mesh::from_off_file("data/dragon.off");
+    // let Some(mesh) = load_mesh("data/bunny.off") else {
+    let Some(mesh) = load_mesh("data/dragon.off") else {
+        return;
+    };
 
     let ambient_color = Vector3::new(0.2, 0.2, 0.2);
-    let light = primitives::Light::new(Vector3::new(-1., 1., 3.), Vector3::new(0.2, 0.5, 0.1));
+    let light = lights::Light::point(Vector3::new(-1., 1., 3.), Vector3::new(0.2, 0.5, 0.1))
+        .with_attenuation(lights::Attenuation::None);
 
     // set the camera
-    let mut camera = primitives::Camera::new(
-        0.8,
-        1.,
-        500,
-        500,
+    let camera = primitives::Camera::new(
+        0.3491,
+        5.,
+        640,
+        480,
         Vector3::new(0., 0., 2.),
-        primitives::CameraKind::ORTHOGRAPHIC,
-        // primitives::CameraKind::PERSPECTIVE,
+        primitives::CameraKind::PERSPECTIVE,
+        // primitives::CameraKind::ORTHOGRAPHIC,
     );
 
     // global params for rasterization are unifrom and program
-    let uniform = Uniform::new(
-        camera.position,
-        camera.focal_length,
-        camera.fov,
-        camera.width as f64 / camera.height as f64,
-        ambient_color,
-        light.position,
-        light.color,
-    );
+    let uniform = Uniform::new(&camera, ambient_color, light);
 
     let program = Program::new(
         raster::vertex_shader,
@@ -161,13 +219,35 @@ fn raster_task() {
     );
 
     // render via rasterization
-    raster::rasterize(&mesh, uniform, program, &mut camera.image);
+    let mut image = Framebuffer::from_element(camera.width, camera.height, Vector4::zeros());
+    let mut depth_buffer = raster::new_depth_buffer(&image);
+    raster::rasterize(
+        &mesh,
+        &uniform,
+        &program,
+        &raster::PipelineState::default(),
+        &mut image,
+        &mut depth_buffer,
+    );
 
-    image_utils::save_as_png(camera, "raster.png");
+    // draw the edges over the shaded mesh and the top levels of its bvh over everything
+    // let wireframe = raster::SolidColor { color: Vector3::new(0., 0., 0.) };
+    // let state = raster::PipelineState::wireframe_overlay();
+    // raster::rasterize(&mesh, &uniform, &wireframe, &state, &mut image, &mut depth_buffer);
+    // let boxes = mesh.bvh.node_boxes(3);
+    // let state = raster::PipelineState::default().with_lines(raster::LineAlgorithm::Wu, 1.);
+    // let mut depth_buffer = raster::new_depth_buffer(&image);
+    // let color = Vector3::new(1., 1., 0.);
+    // raster::draw_boxes(&boxes, color, &uniform, &state, &mut image, &mut depth_buffer);
+
+    image_utils::save_as_png(&image, "raster.png");
 }
 
+// the task is picked by the first argument, ray tracing by default
 fn main() {
-    raytracing_task();
-    // bvh_task();
-    // raster_task();
+    match std::env::args().nth(1).as_deref() {
+        Some("bvh") => bvh_task(),
+        Some("raster") => raster_task(),
+        _ => raytracing_task(),
+    }
 }
@@ -0,0 +1,586 @@
+use crate::composites::{
+    parse_token, parse_vector3, Mesh, MeshGroup, MeshLoadError, MeshLoadWarning, ParseErrorKind,
+};
+use crate::primitives::Material;
+use nalgebra::{Vector2, Vector3};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// one corner of a face as indices into the position, texture coordinate and normal lists
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+// obj indices start at 1, negative ones count back from the last element read so far
+fn resolve_index(token: &str, count: usize) -> Result<usize, ParseErrorKind> {
+    let index: i64 = parse_token(Some(token))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || !(0..count as i64).contains(&resolved) {
+        return Err(ParseErrorKind::IndexOutOfRange { index, count });
+    }
+    Ok(resolved as usize)
+}
+
+// v, v/vt, v//vn or v/vt/vn
+fn parse_corner(token: &str, counts: (usize, usize, usize)) -> Result<Corner, ParseErrorKind> {
+    let mut parts = token.split('/');
+    let position = resolve_index(parts.next().unwrap_or_default(), counts.0)?;
+    let uv = parts
+        .next()
+        .filter(|part| !part.is_empty())
+        .map(|part| resolve_index(part, counts.1))
+        .transpose()?;
+    let normal = parts
+        .next()
+        .filter(|part| !part.is_empty())
+        .map(|part| resolve_index(part, counts.2))
+        .transpose()?;
+    Ok(Corner {
+        position,
+        uv,
+        normal,
+    })
+}
+
+// a material of an mtl file, some statements only make sense once the whole material is read
+struct MtlEntry {
+    name: String,
+    material: Material,
+    dissolve: f64,
+    transmission: Vector3<f64>,
+    reflection: Option<Vector3<f64>>,
+    illumination: u32,
+}
+
+impl MtlEntry {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            material: Material::new(
+                Vector3::new(0.8, 0.8, 0.8),
+                Vector3::zeros(),
+                1.,
+                Vector3::zeros(),
+                Vector3::zeros(),
+            ),
+            dissolve: 1.,
+            transmission: Vector3::new(1., 1., 1.),
+            reflection: None,
+            illumination: 2,
+        }
+    }
+
+    // illumination models 3 to 7 add mirror reflections, colored by Kr or else by the specular
+    // color. the transparent part of a material that is not fully opaque refracts
+    fn finish(mut self) -> (String, Material) {
+        if (3..=7).contains(&self.illumination) {
+            self.material.reflection_color =
+                self.reflection.unwrap_or(self.material.specular_color);
+        }
+        if self.dissolve < 1. {
+            self.material.refraction_color = (1. - self.dissolve) * self.transmission;
+        }
+        (self.name, self.material)
+    }
+}
+
+// Kd, Ks, Kr, Ns, Ni, d/Tr, Tf, Ke and illum are mapped onto the material, everything else such as
+// texture maps is ignored
+fn load_mtl(path: &Path) -> Result<Vec<(String, Material)>, MeshLoadError> {
+    let file = File::open(path).map_err(|error| MeshLoadError::io(path, error))?;
+    let mut materials = Vec::new();
+    let mut current: Option<MtlEntry> = None;
+
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|error| MeshLoadError::io(path, error))?;
+        let mut tokens = line.split_ascii_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            materials.extend(current.take().map(MtlEntry::finish));
+            current = Some(MtlEntry::new(tokens.next().unwrap_or_default()));
+            continue;
+        }
+        let Some(entry) = current.as_mut() else {
+            continue;
+        };
+        parse_mtl_statement(entry, keyword, &mut tokens)
+            .map_err(|kind| MeshLoadError::parse(path, idx + 1, kind))?;
+    }
+    materials.extend(current.map(MtlEntry::finish));
+    Ok(materials)
+}
+
+fn parse_mtl_statement<'a>(
+    entry: &mut MtlEntry,
+    keyword: &str,
+    tokens: &mut impl Iterator<Item = &'a str>,
+) -> Result<(), ParseErrorKind> {
+    match keyword {
+        "Kd" => entry.material.diffuse_color = parse_vector3(tokens)?,
+        "Ks" => entry.material.specular_color = parse_vector3(tokens)?,
+        "Ke" => entry.material.emission = parse_vector3(tokens)?,
+        "Kr" => entry.reflection = Some(parse_vector3(tokens)?),
+        "Tf" => entry.transmission = parse_vector3(tokens)?,
+        "Ns" => entry.material.specular_exponent = parse_token(tokens.next())?,
+        "Ni" => entry.material.refractive_index = parse_token(tokens.next())?,
+        "d" => entry.dissolve = parse_token(tokens.next())?,
+        "Tr" => entry.dissolve = 1. - parse_token::<f64>(tokens.next())?,
+        "illum" => entry.illumination = parse_token(tokens.next())?,
+        _ => {}
+    }
+    Ok(())
+}
+
+// the statements load_mtl maps onto the material. mirror reflections are written as Kr with
+// the illumination model 3, which turns them on
+fn save_mtl(path: &Path, materials: &[(String, Material)]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for (name, material) in materials {
+        writeln!(file, "newmtl {name}")?;
+        for (keyword, color) in [
+            ("Kd", material.diffuse_color),
+            ("Ks", material.specular_color),
+            ("Ke", material.emission),
+        ] {
+            writeln!(file, "{keyword} {} {} {}", color.x, color.y, color.z)?;
+        }
+        writeln!(file, "Ns {}", material.specular_exponent)?;
+        writeln!(file, "Ni {}", material.refractive_index)?;
+        if material.is_transparent() {
+            let t = material.refraction_color;
+            writeln!(file, "d 0")?;
+            writeln!(file, "Tf {} {} {}", t.x, t.y, t.z)?;
+        }
+        if material.reflection_color == Vector3::zeros() {
+            writeln!(file, "illum 2")?;
+        } else {
+            let r = material.reflection_color;
+            writeln!(file, "Kr {} {} {}", r.x, r.y, r.z)?;
+            writeln!(file, "illum 3")?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()
+}
+
+// everything read from an obj file so far
+#[derive(Default)]
+struct ObjContents {
+    positions: Vec<Vector3<f64>>,
+    uvs: Vec<Vector2<f64>>,
+    normals: Vec<Vector3<f64>>,
+    faces: Vec<[Corner; 3]>,
+    groups: Vec<MeshGroup>,
+    materials: Vec<(String, Material)>,
+    material_ids: HashMap<String, usize>,
+    triangle_materials: Vec<Option<usize>>,
+    current_material: Option<usize>,
+}
+
+impl ObjContents {
+    // mtllib is handled by the caller because its errors belong to a different file
+    fn parse_statement<'a>(
+        &mut self,
+        keyword: &str,
+        mut tokens: impl Iterator<Item = &'a str>,
+    ) -> Result<(), ParseErrorKind> {
+        match keyword {
+            "v" => self.positions.push(parse_vector3(&mut tokens)?),
+            "vn" => self.normals.push(parse_vector3(&mut tokens)?),
+            "vt" => {
+                let u = parse_token(tokens.next())?;
+                let v = tokens
+                    .next()
+                    .map_or(Ok(0.), |token| parse_token(Some(token)))?;
+                self.uvs.push(Vector2::new(u, v));
+            }
+            "f" => {
+                let counts = (self.positions.len(), self.uvs.len(), self.normals.len());
+                let corners = tokens
+                    .map(|token| parse_corner(token, counts))
+                    .collect::<Result<Vec<Corner>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(ParseErrorKind::WrongCount {
+                        what: "face corners",
+                        expected: 3,
+                        found: corners.len(),
+                    });
+                }
+                for i in 1..corners.len() - 1 {
+                    self.faces.push([corners[0], corners[i], corners[i + 1]]);
+                    self.triangle_materials.push(self.current_material);
+                    if let Some(group) = self.groups.last_mut() {
+                        group.triangles.end = self.faces.len();
+                    }
+                }
+            }
+            "o" | "g" => {
+                self.drop_empty_group();
+                self.groups.push(MeshGroup {
+                    name: tokens.collect::<Vec<_>>().join(" "),
+                    triangles: self.faces.len()..self.faces.len(),
+                });
+            }
+            // unknown materials fall back to the default material of the mesh
+            "usemtl" => {
+                self.current_material = tokens
+                    .next()
+                    .and_then(|name| self.material_ids.get(name).copied());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // a group without faces is replaced by the next one
+    fn drop_empty_group(&mut self) {
+        if self
+            .groups
+            .last()
+            .is_some_and(|group| group.triangles.is_empty())
+        {
+            self.groups.pop();
+        }
+    }
+
+    fn add_materials(&mut self, materials: Vec<(String, Material)>) {
+        for (name, material) in materials {
+            self.material_ids.insert(name.clone(), self.materials.len());
+            self.materials.push((name, material));
+        }
+    }
+
+    // corners that share their position, texture coordinate and normal become one vertex
+    fn into_mesh(mut self) -> Mesh {
+        self.drop_empty_group();
+        let mut vertex_ids: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+        let mut vertices: Vec<Corner> = Vec::new();
+        let indices = self
+            .faces
+            .iter()
+            .map(|face| {
+                face.map(|corner| {
+                    let key = (corner.position, corner.uv, corner.normal);
+                    *vertex_ids.entry(key).or_insert_with(|| {
+                        vertices.push(corner);
+                        (vertices.len() - 1) as u32
+                    })
+                })
+            })
+            .collect();
+        let positions = vertices
+            .iter()
+            .map(|vertex| self.positions[vertex.position])
+            .collect();
+        let mut mesh = Mesh::new(positions, indices);
+
+        // vertices without a normal keep the computed one
+        for (normal, vertex) in mesh.normals.iter_mut().zip(&vertices) {
+            if let Some(n) = vertex.normal {
+                *normal = self.normals[n].try_normalize(0.).unwrap_or(*normal);
+            }
+        }
+        if vertices.iter().any(|vertex| vertex.uv.is_some()) {
+            mesh.uvs = vertices
+                .iter()
+                .map(|vertex| vertex.uv.map_or(Vector2::zeros(), |uv| self.uvs[uv]))
+                .collect();
+        }
+        mesh.groups = self.groups;
+        if self.triangle_materials.iter().any(Option::is_some) {
+            mesh.materials = self.materials;
+            mesh.triangle_materials = self.triangle_materials;
+        }
+        mesh
+    }
+}
+
+impl Mesh {
+    // polygons are split into triangle fans, lines and points are skipped. mtl libraries are
+    // looked up relative to the obj file. one that is missing or broken is returned as a warning
+    // and its materials fall back to the default one, errors in the geometry still fail the load
+    pub fn from_obj_file(path: &str) -> Result<(Self, Vec<MeshLoadWarning>), MeshLoadError> {
+        let file = File::open(path).map_err(|error| MeshLoadError::io(path, error))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut contents = ObjContents::default();
+        let mut warnings = Vec::new();
+
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|error| MeshLoadError::io(path, error))?;
+            let mut tokens = line.split_ascii_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+            if keyword == "mtllib" {
+                for library in tokens {
+                    match load_mtl(&directory.join(library)) {
+                        Ok(materials) => contents.add_materials(materials),
+                        Err(error) => warnings.push(MeshLoadWarning::MaterialLibrary(error)),
+                    }
+                }
+                continue;
+            }
+            contents
+                .parse_statement(keyword, tokens)
+                .map_err(|kind| MeshLoadError::parse(path, idx + 1, kind))?;
+        }
+        Ok((contents.into_mesh(), warnings))
+    }
+
+    // positions, texture coordinates, normals, groups and materials. every vertex has its own
+    // texture coordinate and normal, so all three indices of a corner are the same. the
+    // materials go into an mtl library next to the obj file with the same name
+    pub fn save_as_obj(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let has_materials = self.triangle_materials.len() == self.indices.len()
+            && self.triangle_materials.iter().any(Option::is_some);
+        if has_materials {
+            let library = Path::new(path).with_extension("mtl");
+            save_mtl(&library, &self.materials)?;
+            let name = library.file_name().unwrap_or_default().to_string_lossy();
+            writeln!(file, "mtllib {name}")?;
+        }
+        for p in &self.positions {
+            writeln!(file, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for uv in &self.uvs {
+            writeln!(file, "vt {} {}", uv.x, uv.y)?;
+        }
+        for n in &self.normals {
+            writeln!(file, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+
+        let has_uvs = self.uvs.len() == self.positions.len();
+        let has_normals = self.normals.len() == self.positions.len();
+        let mut groups = self.groups.iter().peekable();
+        let mut current_material = None;
+        for (idx, face) in self.indices.iter().enumerate() {
+            while let Some(group) = groups.next_if(|group| group.triangles.start <= idx) {
+                writeln!(file, "g {}", group.name)?;
+            }
+            // a usemtl without a name goes back to the default material
+            let material = if has_materials {
+                self.triangle_materials[idx]
+            } else {
+                None
+            };
+            if material != current_material {
+                let name = material.map_or("", |material| self.materials[material].0.as_str());
+                writeln!(file, "usemtl {name}")?;
+                current_material = material;
+            }
+            let [a, b, c] = face.map(|vertex| {
+                let v = vertex + 1;
+                match (has_uvs, has_normals) {
+                    (true, true) => format!("{v}/{v}/{v}"),
+                    (true, false) => format!("{v}/{v}"),
+                    (false, true) => format!("{v}//{v}"),
+                    (false, false) => v.to_string(),
+                }
+            });
+            writeln!(file, "f {a} {b} {c}")?;
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composites::tests::{
+        assert_same_geometry, load_error, parse_error, round_trip_mesh, temp_file,
+    };
+    use crate::composites::MeshGroup;
+
+    fn load_obj(name: &str, contents: &str) -> Result<Mesh, MeshLoadError> {
+        let (mesh, warnings) = Mesh::from_obj_file(&temp_file(name, contents))?;
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(mesh)
+    }
+
+    #[test]
+    fn obj_corners_become_vertices() {
+        let mesh = load_obj(
+            "obj_corners.obj",
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 2\n\
+             g quad\nf 1/1/1 2/2/1 3/3/1 4/4/1\ng tail\nf -4/1 -3/2 -1/4\n",
+        )
+        .unwrap();
+        // the second face uses the same positions without normals, so they are new vertices
+        assert_eq!(mesh.positions.len(), 7);
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3], [4, 5, 6]]);
+        assert_eq!(mesh.normals[0], Vector3::z());
+        assert_eq!(mesh.uvs[2], Vector2::new(1., 1.));
+        let groups: Vec<_> = mesh
+            .groups
+            .iter()
+            .map(|group| (group.name.as_str(), group.triangles.clone()))
+            .collect();
+        assert_eq!(groups, vec![("quad", 0..2), ("tail", 2..3)]);
+    }
+
+    #[test]
+    fn obj_materials() {
+        let library = temp_file(
+            "obj_materials.mtl",
+            "newmtl red\nKd 1 0 0\nillum 3\nKs 0.5 0.5 0.5\n",
+        );
+        let library = Path::new(&library).file_name().unwrap().to_str().unwrap();
+        let mesh = load_obj(
+            "obj_materials.obj",
+            &format!("mtllib {library}\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl red\nf 1 2 3\n"),
+        )
+        .unwrap();
+        assert_eq!(mesh.triangle_materials, vec![None, Some(0)]);
+        let (name, material) = &mesh.materials[0];
+        assert_eq!(name, "red");
+        assert_eq!(material.diffuse_color, Vector3::new(1., 0., 0.));
+        assert_eq!(material.reflection_color, Vector3::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn obj_missing_material_library_is_a_warning() {
+        let path = temp_file(
+            "obj_missing_mtl.obj",
+            "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n",
+        );
+        let (mesh, warnings) = Mesh::from_obj_file(&path).unwrap();
+        assert_eq!(mesh.triangle_count(), 1);
+        assert!(mesh.triangle_materials.is_empty());
+        assert!(matches!(
+            warnings[..],
+            [MeshLoadWarning::MaterialLibrary(MeshLoadError::Io { .. })]
+        ));
+    }
+
+    #[test]
+    fn obj_index_out_of_range() {
+        let result = load_obj("obj_index.obj", "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n");
+        assert_eq!(
+            parse_error(result),
+            (4, ParseErrorKind::IndexOutOfRange { index: 3, count: 2 })
+        );
+        let result = load_obj("obj_zero.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n");
+        assert_eq!(
+            parse_error(result),
+            (4, ParseErrorKind::IndexOutOfRange { index: 0, count: 3 })
+        );
+    }
+
+    #[test]
+    fn obj_invalid_number() {
+        let result = load_obj("obj_number.obj", "v 0 0 0\nv 1 zero 0\n");
+        assert_eq!(
+            parse_error(result),
+            (2, ParseErrorKind::InvalidNumber("zero".to_owned()))
+        );
+        let result = load_obj("obj_missing.obj", "v 0 0\n");
+        assert_eq!(parse_error(result), (1, ParseErrorKind::MissingValue));
+    }
+
+    #[test]
+    fn obj_face_with_two_corners() {
+        let result = load_obj("obj_corners_short.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n");
+        assert_eq!(
+            parse_error(result),
+            (
+                3,
+                ParseErrorKind::WrongCount {
+                    what: "face corners",
+                    expected: 3,
+                    found: 2,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn obj_round_trip() {
+        let mut mesh = round_trip_mesh();
+        mesh.groups = vec![
+            MeshGroup {
+                name: "first".to_owned(),
+                triangles: 0..1,
+            },
+            MeshGroup {
+                name: "rest".to_owned(),
+                triangles: 1..3,
+            },
+        ];
+        let glass = Material::new(
+            Vector3::zeros(),
+            Vector3::new(0.2, 0.2, 0.2),
+            256.,
+            Vector3::new(0.2, 0.2, 0.2),
+            Vector3::new(0.9, 0.9, 1.),
+        )
+        .with_refractive_index(1.5);
+        let light = Material::new(
+            Vector3::new(0.8, 0.7, 0.6),
+            Vector3::zeros(),
+            1.,
+            Vector3::zeros(),
+            Vector3::zeros(),
+        )
+        .with_emission(Vector3::new(4., 4., 3.));
+        // the reflection differs from the specular color, so it can not be derived from it
+        let mirror = Material::new(
+            Vector3::new(0.1, 0.1, 0.1),
+            Vector3::new(0.5, 0.5, 0.5),
+            64.,
+            Vector3::new(0.9, 0.8, 0.7),
+            Vector3::zeros(),
+        );
+        mesh.materials = vec![
+            ("glass".to_owned(), glass),
+            ("light".to_owned(), light),
+            ("mirror".to_owned(), mirror),
+        ];
+        mesh.triangle_materials = vec![Some(2), Some(1), Some(0)];
+
+        let path = temp_file("obj_round_trip.obj", "");
+        mesh.save_as_obj(&path).unwrap();
+        let (loaded, warnings) = Mesh::from_obj_file(&path).unwrap();
+        assert!(warnings.is_empty(), "{warnings:?}");
+        assert_same_geometry(&loaded, &mesh);
+        assert_eq!(loaded.uvs, mesh.uvs);
+        let groups = |mesh: &Mesh| {
+            mesh.groups
+                .iter()
+                .map(|group| (group.name.clone(), group.triangles.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(groups(&loaded), groups(&mesh));
+        assert_eq!(loaded.triangle_materials, mesh.triangle_materials);
+        assert_eq!(loaded.materials.len(), mesh.materials.len());
+        for ((loaded_name, loaded), (name, material)) in
+            loaded.materials.iter().zip(&mesh.materials)
+        {
+            assert_eq!(loaded_name, name);
+            assert_eq!(loaded.diffuse_color, material.diffuse_color);
+            assert_eq!(loaded.specular_color, material.specular_color);
+            assert_eq!(loaded.specular_exponent, material.specular_exponent);
+            assert_eq!(loaded.reflection_color, material.reflection_color);
+            assert_eq!(loaded.refraction_color, material.refraction_color);
+            assert_eq!(loaded.refractive_index, material.refractive_index);
+            assert_eq!(loaded.emission, material.emission);
+        }
+    }
+
+    #[test]
+    fn obj_missing_file() {
+        let error = load_error(Mesh::from_obj_file("does/not/exist.obj").map(|(mesh, _)| mesh));
+        assert!(matches!(error, MeshLoadError::Io { .. }));
+    }
+}
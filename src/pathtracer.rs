@@ -0,0 +1,310 @@
+use crate::{
+    lights::LightSample,
+    primitives::{HitPoint, Material, Ray, Scene, SceneObject},
+    sampling::{self, SampleStream},
+    utils,
+};
+use nalgebra::{Vector2, Vector3, Vector4};
+use std::f64::consts::PI;
+
+// a direction chosen by a bsdf, weight is f * cos / pdf
+struct BsdfSample {
+    direction: Vector3<f64>,
+    weight: Vector3<f64>,
+    pdf: f64,
+    // perfect mirror or refraction, which light sampling can not produce
+    delta: bool,
+}
+
+// the material at a hit point as a mix of a lambertian lobe (diffuse_color), a normalized
+// blinn-phong lobe (specular_color, specular_exponent) and a delta lobe, which is a mirror
+// (reflection_color) or for transparent materials a fresnel weighted dielectric interface.
+// the lobes are sampled proportional to their luminance
+struct Bsdf<'a> {
+    material: &'a Material,
+    normal: Vector3<f64>,
+    tangent: Vector3<f64>,
+    bitangent: Vector3<f64>,
+    front_face: bool,
+    diffuse_probability: f64,
+    glossy_probability: f64,
+}
+
+impl<'a> Bsdf<'a> {
+    fn new(material: &'a Material, hit_point: &HitPoint) -> Self {
+        let diffuse = utils::luminance(&material.diffuse_color);
+        let glossy = utils::luminance(&material.specular_color);
+        let delta = if material.is_transparent() {
+            utils::luminance(&material.reflection_color)
+                .max(utils::luminance(&material.refraction_color))
+        } else {
+            utils::luminance(&material.reflection_color)
+        };
+        let total = diffuse + glossy + delta;
+        let (diffuse_probability, glossy_probability) = if total > 0. {
+            (diffuse / total, glossy / total)
+        } else {
+            (0., 0.)
+        };
+
+        let (tangent, bitangent) = utils::coordinate_system(&hit_point.shading_normal);
+        Self {
+            material,
+            normal: hit_point.shading_normal,
+            tangent,
+            bitangent,
+            front_face: hit_point.front_face,
+            diffuse_probability,
+            glossy_probability,
+        }
+    }
+
+    fn delta_probability(&self) -> f64 {
+        if self.material.reflection_color == Vector3::zeros() && !self.material.is_transparent() {
+            return 0.;
+        }
+        1. - self.diffuse_probability - self.glossy_probability
+    }
+
+    fn is_delta(&self) -> bool {
+        self.diffuse_probability + self.glossy_probability == 0.
+    }
+
+    fn to_world(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        v.x * self.tangent + v.y * self.bitangent + v.z * self.normal
+    }
+
+    // value of the non delta lobes, wo and wi point away from the surface
+    fn evaluate(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Vector3<f64> {
+        let (cos_o, cos_i) = (self.normal.dot(wo), self.normal.dot(wi));
+        if cos_o <= 0. || cos_i <= 0. {
+            return Vector3::zeros();
+        }
+        let exponent = self.material.specular_exponent;
+        let half = (wo + wi).normalize();
+        let glossy = (exponent + 8.) / (8. * PI) * self.normal.dot(&half).max(0.).powf(exponent);
+        self.material.diffuse_color / PI + glossy * self.material.specular_color
+    }
+
+    // density of sampling wi from wo with the non delta lobes
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let (cos_o, cos_i) = (self.normal.dot(wo), self.normal.dot(wi));
+        if cos_o <= 0. || cos_i <= 0. {
+            return 0.;
+        }
+        let exponent = self.material.specular_exponent;
+        let half = (wo + wi).normalize();
+        let half_pdf = (exponent + 1.) / (2. * PI) * self.normal.dot(&half).max(0.).powf(exponent);
+        self.diffuse_probability * cos_i / PI
+            + self.glossy_probability * half_pdf / (4. * wo.dot(&half))
+    }
+
+    fn sample(&self, wo: &Vector3<f64>, u_lobe: f64, u: &Vector2<f64>) -> Option<BsdfSample> {
+        if u_lobe < self.diffuse_probability + self.glossy_probability {
+            let wi = if u_lobe < self.diffuse_probability {
+                self.to_world(&sampling::sample_cosine_hemisphere(u))
+            } else {
+                let exponent = self.material.specular_exponent;
+                let cos_theta = u.x.powf(1. / (exponent + 1.));
+                let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+                let phi = 2. * PI * u.y;
+                let half = self.to_world(&Vector3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+                utils::reflect(&-wo, &half)
+            };
+
+            let pdf = self.pdf(wo, &wi);
+            if pdf <= 0. {
+                return None;
+            }
+            return Some(BsdfSample {
+                direction: wi,
+                weight: self.evaluate(wo, &wi) * self.normal.dot(&wi) / pdf,
+                pdf,
+                delta: false,
+            });
+        }
+
+        let delta_probability = self.delta_probability();
+        if delta_probability <= 0. {
+            return None;
+        }
+        // reuse the part of u_lobe that falls into the delta lobe for the fresnel choice
+        let u_fresnel =
+            (u_lobe - self.diffuse_probability - self.glossy_probability) / delta_probability;
+        let reflection = BsdfSample {
+            direction: utils::reflect(&-wo, &self.normal),
+            weight: self.material.reflection_color / delta_probability,
+            pdf: delta_probability,
+            delta: true,
+        };
+        if !self.material.is_transparent() {
+            return Some(reflection);
+        }
+
+        let (eta_i, eta_t) = if self.front_face {
+            (1., self.material.refractive_index)
+        } else {
+            (self.material.refractive_index, 1.)
+        };
+        let reflectance = utils::fresnel_dielectric(self.normal.dot(wo), eta_i, eta_t);
+        if u_fresnel < reflectance {
+            return Some(reflection);
+        }
+        utils::refract(&-wo, &self.normal, eta_i / eta_t).map(|direction| BsdfSample {
+            direction,
+            weight: self.material.refraction_color / delta_probability,
+            pdf: delta_probability,
+            delta: true,
+        })
+    }
+}
+
+// solid angle density with which light sampling picks the hit point on an emissive object
+fn emitter_pdf(object: &SceneObject, hit_point: &HitPoint, ray: &Ray) -> f64 {
+    let area = object.shape.area();
+    if area <= 0. {
+        // not an area light, only bsdf sampling can find it
+        return 0.;
+    }
+    let distance = (hit_point.point - ray.origin).norm();
+    distance * distance / (hit_point.normal.dot(&-ray.direction) * area)
+}
+
+// weight of a sample from strategy f when strategy g could have produced it as well (veach)
+fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let (f2, g2) = (f_pdf * f_pdf, g_pdf * g_pdf);
+    if f2 + g2 > 0. {
+        f2 / (f2 + g2)
+    } else {
+        0.
+    }
+}
+
+// light arriving directly from the lights at the hit point (next event estimation)
+fn direct_lighting(
+    bsdf: &Bsdf,
+    hit_point: &HitPoint,
+    wo: &Vector3<f64>,
+    scene: &Scene,
+    samples: &mut SampleStream,
+) -> Vector3<f64> {
+    let mut radiance = Vector3::zeros();
+    for light in &scene.lights {
+        let u = samples.next_2d();
+        if let Some(light_sample) = light.sample(scene, &hit_point.point, &u) {
+            radiance += light_contribution(bsdf, hit_point, wo, scene, &light_sample);
+        }
+    }
+    // the background lights the scene as well
+    if let Some(background) = &scene.background {
+        if let Some(light_sample) = background.sample(&samples.next_2d()) {
+            radiance += light_contribution(bsdf, hit_point, wo, scene, &light_sample);
+        }
+    }
+    radiance
+}
+
+fn light_contribution(
+    bsdf: &Bsdf,
+    hit_point: &HitPoint,
+    wo: &Vector3<f64>,
+    scene: &Scene,
+    light_sample: &LightSample,
+) -> Vector3<f64> {
+    let wi = light_sample.direction;
+    let f = bsdf.evaluate(wo, &wi);
+    if f == Vector3::zeros() || !utils::is_light_visible(light_sample, hit_point, scene) {
+        return Vector3::zeros();
+    }
+    let weight = if light_sample.delta {
+        1.
+    } else {
+        power_heuristic(light_sample.pdf, bsdf.pdf(wo, &wi))
+    };
+    f.component_mul(&light_sample.radiance) * bsdf.normal.dot(&wi) * weight / light_sample.pdf
+}
+
+// estimate the light arriving along the ray with a random walk through the scene. every vertex
+// adds the direct light from the lights, then the path continues in a direction sampled from the
+// bsdf. emitters found by the bsdf sample are weighted against light sampling with multiple
+// importance sampling. after rr_depth bounces paths are terminated at random with a probability
+// that keeps the estimate unbiased
+pub fn trace_path(
+    ray: &Ray,
+    scene: &Scene,
+    max_depth: usize,
+    rr_depth: usize,
+    samples: &mut SampleStream,
+) -> Vector4<f64> {
+    let mut radiance = Vector3::zeros();
+    let mut throughput = Vector3::new(1., 1., 1.);
+    let mut ray = *ray;
+    let mut alpha = 0.;
+    // density of the bsdf sample that created the current ray, camera rays count as delta
+    let mut previous_pdf = 1.;
+    let mut previous_delta = true;
+
+    for depth in 0..=max_depth {
+        let Some((object, hit_point)) = utils::find_closest_point(&ray, scene) else {
+            if let Some(background) = &scene.background {
+                let weight = if previous_delta {
+                    1.
+                } else {
+                    power_heuristic(previous_pdf, background.pdf(&ray.direction))
+                };
+                radiance += weight * throughput.component_mul(&background.radiance(&ray.direction));
+                if depth == 0 {
+                    alpha = 1.;
+                }
+            }
+            break;
+        };
+        if depth == 0 {
+            alpha = 1.;
+        }
+
+        let material = scene.material(object.material);
+        if material.is_emissive() && hit_point.front_face {
+            let weight = if previous_delta {
+                1.
+            } else {
+                power_heuristic(previous_pdf, emitter_pdf(object, &hit_point, &ray))
+            };
+            radiance += weight * throughput.component_mul(&material.emission);
+        }
+
+        let bsdf = Bsdf::new(material, &hit_point);
+        let wo = -ray.direction;
+        if !bsdf.is_delta() {
+            let direct = direct_lighting(&bsdf, &hit_point, &wo, scene, samples);
+            radiance += throughput.component_mul(&direct);
+        }
+        if depth == max_depth {
+            break;
+        }
+
+        let u_lobe = samples.next_1d();
+        let Some(bsdf_sample) = bsdf.sample(&wo, u_lobe, &samples.next_2d()) else {
+            break;
+        };
+        throughput.component_mul_assign(&bsdf_sample.weight);
+        previous_pdf = bsdf_sample.pdf;
+        previous_delta = bsdf_sample.delta;
+
+        if depth >= rr_depth {
+            let survival = throughput.max().min(0.95);
+            if samples.next_1d() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+
+        ray = hit_point.spawn_ray(bsdf_sample.direction);
+    }
+
+    Vector4::new(radiance.x, radiance.y, radiance.z, alpha)
+}
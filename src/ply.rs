@@ -0,0 +1,742 @@
+use crate::composites::{parse_token, Mesh, MeshLoadError, ParseErrorKind};
+use nalgebra::{Vector2, Vector3};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    // both the original and the sized type names are in use
+    fn parse(name: &str) -> Result<Self, ParseErrorKind> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::Int8,
+            "uchar" | "uint8" => ScalarType::UInt8,
+            "short" | "int16" => ScalarType::Int16,
+            "ushort" | "uint16" => ScalarType::UInt16,
+            "int" | "int32" => ScalarType::Int32,
+            "uint" | "uint32" => ScalarType::UInt32,
+            "float" | "float32" => ScalarType::Float32,
+            "double" | "float64" => ScalarType::Float64,
+            _ => {
+                return Err(ParseErrorKind::Unsupported(format!(
+                    "property type {name:?}"
+                )))
+            }
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    // integer colors go up to the largest value of their type, float colors up to 1
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => u8::MAX as f64,
+            ScalarType::Int16 | ScalarType::UInt16 => u16::MAX as f64,
+            ScalarType::Int32 | ScalarType::UInt32 => u32::MAX as f64,
+            ScalarType::Float32 | ScalarType::Float64 => 1.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    // a number of items followed by the items
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn property(&self, name: &str) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| property.name == name)
+    }
+
+    // indices of all the named properties, None if any is missing
+    fn properties<const N: usize>(&self, names: [&str; N]) -> Option<[usize; N]> {
+        let slots = names.map(|name| self.property(name));
+        if slots.iter().any(Option::is_none) {
+            return None;
+        }
+        Some(slots.map(Option::unwrap_or_default))
+    }
+}
+
+// which properties of the vertex element hold the attributes of a vertex
+struct VertexLayout {
+    position: [usize; 3],
+    normal: Option<[usize; 3]>,
+    uv: Option<[usize; 2]>,
+    color: Option<[usize; 3]>,
+}
+
+impl VertexLayout {
+    fn new(element: &Element) -> Result<Self, ParseErrorKind> {
+        let position = element
+            .properties(["x", "y", "z"])
+            .ok_or_else(|| ParseErrorKind::MissingProperty("x".to_owned()))?;
+        // texture coordinates go by several names
+        let uv = [["u", "v"], ["s", "t"], ["texture_u", "texture_v"]]
+            .into_iter()
+            .find_map(|names| element.properties(names));
+        Ok(Self {
+            position,
+            normal: element.properties(["nx", "ny", "nz"]),
+            uv,
+            color: element.properties(["red", "green", "blue"]),
+        })
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    // number of lines up to and including end_header
+    lines: usize,
+    // byte offset of the data after end_header
+    body: usize,
+}
+
+fn parse_header(data: &[u8], path: &Path) -> Result<Header, MeshLoadError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut position = 0;
+    let mut line_number = 0;
+
+    while position < data.len() {
+        let end = data[position..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(data.len(), |end| position + end);
+        let line = String::from_utf8_lossy(&data[position..end]);
+        position = (end + 1).min(data.len());
+        line_number += 1;
+
+        let mut tokens = line.split_ascii_whitespace();
+        let keyword = tokens.next().unwrap_or_default();
+        let error = |kind| MeshLoadError::parse(path, line_number, kind);
+        if line_number == 1 {
+            if keyword != "ply" {
+                return Err(error(ParseErrorKind::BadHeader(line.trim().to_owned())));
+            }
+            continue;
+        }
+        match keyword {
+            "format" => {
+                format = Some(match tokens.next().unwrap_or_default() {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    other => {
+                        let kind = ParseErrorKind::Unsupported(format!("format {other:?}"));
+                        return Err(error(kind));
+                    }
+                });
+            }
+            "element" => {
+                let name = tokens.next().unwrap_or_default().to_owned();
+                let count = parse_token(tokens.next()).map_err(error)?;
+                elements.push(Element {
+                    name,
+                    count,
+                    properties: Vec::new(),
+                });
+            }
+            "property" => {
+                let Some(element) = elements.last_mut() else {
+                    return Err(error(ParseErrorKind::BadHeader(line.trim().to_owned())));
+                };
+                let kind = match tokens.next().ok_or(ParseErrorKind::MissingValue) {
+                    Ok("list") => {
+                        let count = ScalarType::parse(tokens.next().unwrap_or_default());
+                        let item = ScalarType::parse(tokens.next().unwrap_or_default());
+                        PropertyKind::List {
+                            count: count.map_err(error)?,
+                            item: item.map_err(error)?,
+                        }
+                    }
+                    Ok(name) => PropertyKind::Scalar(ScalarType::parse(name).map_err(error)?),
+                    Err(kind) => return Err(error(kind)),
+                };
+                let name = tokens.next().ok_or(ParseErrorKind::MissingValue);
+                element.properties.push(Property {
+                    name: name.map_err(error)?.to_owned(),
+                    kind,
+                });
+            }
+            "end_header" => {
+                let format = format.ok_or_else(|| error(ParseErrorKind::MissingValue))?;
+                return Ok(Header {
+                    format,
+                    elements,
+                    lines: line_number,
+                    body: position,
+                });
+            }
+            // comment, obj_info and blank lines
+            _ => {}
+        }
+    }
+
+    let kind = ParseErrorKind::WrongCount {
+        what: "end_header lines",
+        expected: 1,
+        found: 0,
+    };
+    Err(MeshLoadError::parse(path, 0, kind))
+}
+
+// where the values of the elements come from, one line per element in ascii files
+trait ValueSource {
+    // move on to the next element, the arguments describe the error if there is none
+    fn next_element(
+        &mut self,
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    ) -> Result<(), MeshLoadError>;
+
+    fn value(&mut self, scalar: ScalarType) -> Result<f64, MeshLoadError>;
+
+    // error at the current element
+    fn error(&self, kind: ParseErrorKind) -> MeshLoadError;
+}
+
+struct AsciiSource<'a, L: Iterator<Item = (usize, &'a str)>> {
+    lines: L,
+    tokens: std::str::SplitAsciiWhitespace<'a>,
+    line_number: usize,
+    path: &'a Path,
+}
+
+impl<'a, L: Iterator<Item = (usize, &'a str)>> ValueSource for AsciiSource<'a, L> {
+    fn next_element(
+        &mut self,
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    ) -> Result<(), MeshLoadError> {
+        for (line_number, line) in self.lines.by_ref() {
+            if !line.trim().is_empty() {
+                self.tokens = line.split_ascii_whitespace();
+                self.line_number = line_number;
+                return Ok(());
+            }
+        }
+        let kind = ParseErrorKind::WrongCount {
+            what,
+            expected,
+            found,
+        };
+        Err(MeshLoadError::parse(self.path, 0, kind))
+    }
+
+    fn value(&mut self, _scalar: ScalarType) -> Result<f64, MeshLoadError> {
+        parse_token(self.tokens.next()).map_err(|kind| self.error(kind))
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> MeshLoadError {
+        MeshLoadError::parse(self.path, self.line_number, kind)
+    }
+}
+
+struct BinarySource<'a> {
+    data: &'a [u8],
+    position: usize,
+    big_endian: bool,
+    path: &'a Path,
+    // start of the current element and what running out of data in it means
+    element_start: usize,
+    missing: ParseErrorKind,
+}
+
+impl ValueSource for BinarySource<'_> {
+    fn next_element(
+        &mut self,
+        what: &'static str,
+        expected: usize,
+        found: usize,
+    ) -> Result<(), MeshLoadError> {
+        self.element_start = self.position;
+        self.missing = ParseErrorKind::WrongCount {
+            what,
+            expected,
+            found,
+        };
+        Ok(())
+    }
+
+    fn value(&mut self, scalar: ScalarType) -> Result<f64, MeshLoadError> {
+        let Some(bytes) = self.data.get(self.position..self.position + scalar.size()) else {
+            return Err(MeshLoadError::parse(self.path, 0, self.missing.clone()));
+        };
+        self.position += scalar.size();
+
+        macro_rules! decode {
+            ($type:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                (if self.big_endian {
+                    <$type>::from_be_bytes(bytes)
+                } else {
+                    <$type>::from_le_bytes(bytes)
+                }) as f64
+            }};
+        }
+        Ok(match scalar {
+            ScalarType::Int8 => bytes[0] as i8 as f64,
+            ScalarType::UInt8 => bytes[0] as f64,
+            ScalarType::Int16 => decode!(i16),
+            ScalarType::UInt16 => decode!(u16),
+            ScalarType::Int32 => decode!(i32),
+            ScalarType::UInt32 => decode!(u32),
+            ScalarType::Float32 => decode!(f32),
+            ScalarType::Float64 => decode!(f64),
+        })
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> MeshLoadError {
+        MeshLoadError::binary(self.path, self.element_start, kind)
+    }
+}
+
+// everything read from the elements of a ply file
+#[derive(Default)]
+struct PlyContents {
+    positions: Vec<Vector3<f64>>,
+    normals: Vec<Vector3<f64>>,
+    uvs: Vec<Vector2<f64>>,
+    colors: Vec<Vector3<f64>>,
+    indices: Vec<[u32; 3]>,
+}
+
+// the vertex and face elements are read, every other element is skipped
+fn read_elements(
+    header: &Header,
+    source: &mut impl ValueSource,
+    path: &Path,
+) -> Result<PlyContents, MeshLoadError> {
+    let num_vertices = header
+        .elements
+        .iter()
+        .find(|element| element.name == "vertex")
+        .map_or(0, |element| element.count);
+    let mut contents = PlyContents::default();
+
+    for element in &header.elements {
+        let header_error = |kind| MeshLoadError::parse(path, header.lines, kind);
+        let (what, layout, corners) = match element.name.as_str() {
+            "vertex" => {
+                let layout = VertexLayout::new(element).map_err(header_error)?;
+                ("vertices", Some(layout), None)
+            }
+            "face" => {
+                let corners = element
+                    .property("vertex_indices")
+                    .or_else(|| element.property("vertex_index"))
+                    .ok_or_else(|| {
+                        header_error(ParseErrorKind::MissingProperty("vertex_indices".to_owned()))
+                    })?;
+                ("faces", None, Some(corners))
+            }
+            _ => ("elements", None, None),
+        };
+
+        let mut values = vec![0.; element.properties.len()];
+        let mut face = Vec::new();
+        for found in 0..element.count {
+            source.next_element(what, element.count, found)?;
+            face.clear();
+            for (k, property) in element.properties.iter().enumerate() {
+                match property.kind {
+                    PropertyKind::Scalar(scalar) => values[k] = source.value(scalar)?,
+                    PropertyKind::List { count, item } => {
+                        let length = source.value(count)?;
+                        if length < 0. || length.fract() != 0. {
+                            return Err(
+                                source.error(ParseErrorKind::InvalidNumber(length.to_string()))
+                            );
+                        }
+                        for _ in 0..length as usize {
+                            let value = source.value(item)?;
+                            if corners == Some(k) {
+                                face.push(value);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(layout) = &layout {
+                contents
+                    .positions
+                    .push(Vector3::from(layout.position.map(|k| values[k])));
+                if let Some(normal) = layout.normal {
+                    contents
+                        .normals
+                        .push(Vector3::from(normal.map(|k| values[k])));
+                }
+                if let Some(uv) = layout.uv {
+                    contents.uvs.push(Vector2::from(uv.map(|k| values[k])));
+                }
+                if let Some(color) = layout.color {
+                    contents.colors.push(Vector3::from(color.map(|k| {
+                        let PropertyKind::Scalar(scalar) = element.properties[k].kind else {
+                            return 0.;
+                        };
+                        values[k] / scalar.color_scale()
+                    })));
+                }
+            }
+            if corners.is_some() {
+                add_face(&mut contents.indices, &face, num_vertices)
+                    .map_err(|kind| source.error(kind))?;
+            }
+        }
+    }
+    Ok(contents)
+}
+
+// polygons are split into triangle fans
+fn add_face(
+    indices: &mut Vec<[u32; 3]>,
+    face: &[f64],
+    num_vertices: usize,
+) -> Result<(), ParseErrorKind> {
+    if face.len() < 3 {
+        return Err(ParseErrorKind::WrongCount {
+            what: "face corners",
+            expected: 3,
+            found: face.len(),
+        });
+    }
+    for &index in face {
+        if index < 0. || index >= num_vertices as f64 || index.fract() != 0. {
+            return Err(ParseErrorKind::IndexOutOfRange {
+                index: index as i64,
+                count: num_vertices,
+            });
+        }
+    }
+    for i in 1..face.len() - 1 {
+        indices.push([face[0], face[i], face[i + 1]].map(|index| index as u32));
+    }
+    Ok(())
+}
+
+impl Mesh {
+    // ascii and binary ply files in either byte order. vertices need x, y and z, their normals
+    // (nx, ny, nz), texture coordinates (u, v) and colors (red, green, blue) are kept when present
+    pub fn from_ply_file(path: &str) -> Result<Self, MeshLoadError> {
+        let data = fs::read(path).map_err(|error| MeshLoadError::io(path, error))?;
+        let path = Path::new(path);
+        let header = parse_header(&data, path)?;
+
+        let contents = match header.format {
+            Format::Ascii => {
+                let text = String::from_utf8_lossy(&data[header.body..]);
+                let mut source = AsciiSource {
+                    lines: text
+                        .lines()
+                        .enumerate()
+                        .map(|(idx, line)| (header.lines + idx + 1, line)),
+                    tokens: "".split_ascii_whitespace(),
+                    line_number: header.lines,
+                    path,
+                };
+                read_elements(&header, &mut source, path)?
+            }
+            Format::BinaryLittleEndian | Format::BinaryBigEndian => {
+                let mut source = BinarySource {
+                    data: &data,
+                    position: header.body,
+                    big_endian: header.format == Format::BinaryBigEndian,
+                    path,
+                    element_start: 0,
+                    missing: ParseErrorKind::MissingValue,
+                };
+                read_elements(&header, &mut source, path)?
+            }
+        };
+
+        let mut mesh = Self::new(contents.positions, contents.indices);
+        // normals that cannot be normalized are replaced by the computed ones
+        for (normal, read) in mesh.normals.iter_mut().zip(&contents.normals) {
+            *normal = read.try_normalize(0.).unwrap_or(*normal);
+        }
+        mesh.uvs = contents.uvs;
+        mesh.colors = contents.colors;
+        Ok(mesh)
+    }
+
+    // binary little endian ply with double precision vertices, so nothing is lost when the file
+    // is loaded again. normals, texture coordinates and colors are written when present
+    pub fn save_as_ply(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let count = self.positions.len();
+        let has_normals = self.normals.len() == count;
+        let has_uvs = self.uvs.len() == count;
+        let has_colors = self.colors.len() == count;
+
+        writeln!(file, "ply")?;
+        writeln!(file, "format binary_little_endian 1.0")?;
+        writeln!(file, "element vertex {count}")?;
+        let mut properties = vec!["x", "y", "z"];
+        if has_normals {
+            properties.extend(["nx", "ny", "nz"]);
+        }
+        if has_uvs {
+            properties.extend(["u", "v"]);
+        }
+        for name in properties {
+            writeln!(file, "property double {name}")?;
+        }
+        if has_colors {
+            for name in ["red", "green", "blue"] {
+                writeln!(file, "property uchar {name}")?;
+            }
+        }
+        writeln!(file, "element face {}", self.indices.len())?;
+        writeln!(file, "property list uchar uint vertex_indices")?;
+        writeln!(file, "end_header")?;
+
+        for idx in 0..count {
+            let mut values = self.positions[idx].as_slice().to_vec();
+            if has_normals {
+                values.extend(self.normals[idx].iter());
+            }
+            if has_uvs {
+                values.extend(self.uvs[idx].iter());
+            }
+            for value in values {
+                file.write_all(&value.to_le_bytes())?;
+            }
+            if has_colors {
+                let color = self.colors[idx].map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+                file.write_all(color.as_slice())?;
+            }
+        }
+        for face in &self.indices {
+            file.write_all(&[3])?;
+            for index in face {
+                file.write_all(&index.to_le_bytes())?;
+            }
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composites::tests::{
+        assert_same_geometry, load_error, parse_error, round_trip_mesh, temp_file,
+    };
+
+    fn load_ply(name: &str, contents: impl AsRef<[u8]>) -> Result<Mesh, MeshLoadError> {
+        Mesh::from_ply_file(&temp_file(name, contents))
+    }
+
+    const VERTEX_HEADER: &str = "element vertex 4\nproperty float x\nproperty float y\n\
+                                 property float z\nelement face 1\n\
+                                 property list uchar int vertex_indices\nend_header\n";
+
+    // a unit quad in the binary format with the given byte order
+    fn binary_quad(
+        format: &str,
+        to_bytes: fn(f32) -> [u8; 4],
+        index: fn(i32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut data = format!("ply\nformat {format} 1.0\n{VERTEX_HEADER}").into_bytes();
+        for position in [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]] {
+            for value in position {
+                data.extend(to_bytes(value));
+            }
+        }
+        data.push(4);
+        for corner in 0..4 {
+            data.extend(index(corner));
+        }
+        data
+    }
+
+    #[test]
+    fn ply_ascii_with_attributes() {
+        let mesh = load_ply(
+            "ply_ascii.ply",
+            "ply\nformat ascii 1.0\ncomment a quad\nelement vertex 4\nproperty float x\n\
+             property float y\nproperty float z\nproperty float nx\nproperty float ny\n\
+             property float nz\nproperty float s\nproperty float t\nproperty uchar red\n\
+             property uchar green\nproperty uchar blue\nelement face 1\n\
+             property list uchar int vertex_index\nend_header\n\
+             0 0 0 0 0 2 0 0 255 0 0\n1 0 0 0 0 1 1 0 0 255 0\n1 1 0 0 0 1 1 1 0 0 255\n\
+             0 1 0 0 0 1 0 1 0 0 0\n4 0 1 2 3\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.normals[0], Vector3::z());
+        assert_eq!(mesh.uvs[2], Vector2::new(1., 1.));
+        assert_eq!(mesh.colors[1], Vector3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn ply_binary_byte_orders() {
+        for (name, data) in [
+            (
+                "ply_le.ply",
+                binary_quad("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes),
+            ),
+            (
+                "ply_be.ply",
+                binary_quad("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes),
+            ),
+        ] {
+            let mesh = load_ply(name, data).unwrap();
+            assert_eq!(mesh.positions[2], Vector3::new(1., 1., 0.));
+            assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        }
+    }
+
+    #[test]
+    fn ply_bad_header() {
+        let result = load_ply("ply_magic.ply", "plx\nformat ascii 1.0\nend_header\n");
+        assert_eq!(
+            parse_error(result),
+            (1, ParseErrorKind::BadHeader("plx".to_owned()))
+        );
+        let result = load_ply("ply_format.ply", "ply\nformat binary_middle_endian 1.0\n");
+        assert_eq!(
+            parse_error(result),
+            (
+                2,
+                ParseErrorKind::Unsupported("format \"binary_middle_endian\"".to_owned())
+            )
+        );
+        let result = load_ply(
+            "ply_no_indices.ply",
+            "ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nproperty float y\n\
+             property float z\nelement face 0\nproperty list uchar int corners\nend_header\n",
+        );
+        assert_eq!(
+            parse_error(result),
+            (
+                9,
+                ParseErrorKind::MissingProperty("vertex_indices".to_owned())
+            )
+        );
+    }
+
+    #[test]
+    fn ply_ascii_errors() {
+        let header = format!("ply\nformat ascii 1.0\n{VERTEX_HEADER}");
+        let result = load_ply("ply_short.ply", format!("{header}0 0 0\n1 0 0\n"));
+        assert_eq!(
+            parse_error(result),
+            (
+                0,
+                ParseErrorKind::WrongCount {
+                    what: "vertices",
+                    expected: 4,
+                    found: 2,
+                }
+            )
+        );
+        let result = load_ply(
+            "ply_index.ply",
+            format!("{header}0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 4\n"),
+        );
+        assert_eq!(
+            parse_error(result),
+            (14, ParseErrorKind::IndexOutOfRange { index: 4, count: 4 })
+        );
+        let result = load_ply("ply_number.ply", format!("{header}0 0 0\n1 one 0\n"));
+        assert_eq!(
+            parse_error(result),
+            (11, ParseErrorKind::InvalidNumber("one".to_owned()))
+        );
+    }
+
+    #[test]
+    fn ply_binary_errors() {
+        let mut data = binary_quad("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        let header_size = data.len() - 4 * 12 - 1 - 4 * 4;
+        *data.last_mut().unwrap() = 7;
+        let error = load_error(load_ply("ply_binary_index.ply", &data));
+        let MeshLoadError::Binary { offset, kind, .. } = error else {
+            panic!("expected a binary error, got {error}");
+        };
+        // the offset is the start of the face
+        assert_eq!(offset, header_size + 4 * 12);
+        assert_eq!(
+            kind,
+            ParseErrorKind::IndexOutOfRange {
+                index: 0x07000003,
+                count: 4,
+            }
+        );
+
+        data.truncate(header_size + 4 * 12 + 3);
+        assert_eq!(
+            parse_error(load_ply("ply_binary_short.ply", &data)),
+            (
+                0,
+                ParseErrorKind::WrongCount {
+                    what: "faces",
+                    expected: 1,
+                    found: 0,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn ply_round_trip() {
+        let mut mesh = round_trip_mesh();
+        mesh.colors = [0, 51, 128, 255]
+            .map(|c| Vector3::new(c as f64 / 255., 1. - c as f64 / 255., 0.))
+            .to_vec();
+        let path = temp_file("ply_round_trip.ply", "");
+        mesh.save_as_ply(&path).unwrap();
+        let loaded = Mesh::from_ply_file(&path).unwrap();
+        assert_same_geometry(&loaded, &mesh);
+        assert_eq!(loaded.uvs, mesh.uvs);
+        assert_eq!(loaded.colors, mesh.colors);
+    }
+}
@@ -1,24 +1,50 @@
-use nalgebra::{DMatrix, Matrix3, Vector3, Vector4};
+use crate::background::Background;
+use crate::datastructures::{AlignedBox3d, BvhBuildOptions, BVH};
+use crate::lights::Light;
+use crate::{sampling, utils};
+use nalgebra::{Matrix3, Matrix4, Orthographic3, Perspective3, Vector2, Vector3};
+use std::f64::consts::PI;
 
 pub enum CameraKind {
     ORTHOGRAPHIC,
     PERSPECTIVE,
 }
 
+// shape of the lens aperture, which is also the shape of out of focus highlights
+#[derive(Debug, Clone, Copy)]
+pub enum ApertureShape {
+    Disk,
+    // regular polygon with the given number of blades, rotated by an angle in radians
+    Polygon { blades: usize, rotation: f64 },
+}
+
 pub struct Camera {
     pub fov: f64,
     pub focal_length: f64,
     pub width: usize,
     pub height: usize,
-    pub image: DMatrix<Vector4<f64>>,
     pub position: Vector3<f64>,
     pub kind: CameraKind,
+    // orthonormal camera to world basis: right, up and backward, the camera looks down -w
+    pub u: Vector3<f64>,
+    pub v: Vector3<f64>,
+    pub w: Vector3<f64>,
+    // thin lens, an aperture radius of zero gives a pinhole camera
+    pub aperture_radius: f64,
+    pub aperture_shape: ApertureShape,
+    // distance along the view direction of the plane that is in focus
+    pub focus_distance: f64,
+    // depth range that the rasterizer draws, the ray tracer sees everything in front of the camera
+    pub near: f64,
+    pub far: f64,
+    // image plane in camera coordinates
     screen_origin: Vector3<f64>,
     x_displacement: Vector3<f64>,
     y_displacement: Vector3<f64>,
 }
 
 impl Camera {
+    // camera looking down -z with y up
     pub fn new(
         fov: f64,
         focal_length: f64,
@@ -27,10 +53,48 @@ impl Camera {
         position: Vector3<f64>,
         kind: CameraKind,
     ) -> Self {
+        Self::look_at(
+            fov,
+            focal_length,
+            width,
+            height,
+            position,
+            position - Vector3::z(),
+            Vector3::y(),
+            kind,
+        )
+    }
+
+    // camera at eye looking at target, up only needs to be roughly perpendicular to the view direction
+    #[allow(clippy::too_many_arguments)]
+    pub fn look_at(
+        fov: f64,
+        focal_length: f64,
+        width: usize,
+        height: usize,
+        eye: Vector3<f64>,
+        target: Vector3<f64>,
+        up: Vector3<f64>,
+        kind: CameraKind,
+    ) -> Self {
+        let w = (eye - target).normalize();
+        let mut u = up.cross(&w);
+        if u.norm_squared() < 1e-12 {
+            // up is parallel to the view direction, any perpendicular vector will do
+            let helper = if w.x.abs() < 0.9 {
+                Vector3::x()
+            } else {
+                Vector3::y()
+            };
+            u = helper.cross(&w);
+        }
+        let u = u.normalize();
+        let v = w.cross(&u);
+
         let aspect_ratio = width as f64 / height as f64;
         let image_y = 2. * (fov / 2.0).tan() * focal_length;
         let image_x = image_y * aspect_ratio;
-        let screen_origin = Vector3::new(-image_x, image_y, position.z - focal_length);
+        let screen_origin = Vector3::new(-image_x, image_y, -focal_length);
         let x_displacement = Vector3::new(2.0 / width as f64 * image_x, 0., 0.);
         let y_displacement = Vector3::new(0., -2.0 / height as f64 * image_y, 0.);
 
@@ -42,53 +106,189 @@ impl Camera {
             x_displacement,
             y_displacement,
             screen_origin,
-            image: DMatrix::from_element(width, height, Vector4::zeros()),
-            position,
+            position: eye,
             kind,
+            u,
+            v,
+            w,
+            aperture_radius: 0.,
+            aperture_shape: ApertureShape::Disk,
+            focus_distance: focal_length,
+            near: 0.01,
+            far: 1000.,
         }
     }
 
-    pub fn ray(&self, i: usize, j: usize) -> Ray {
-        let screen_point = self.screen_origin
-            + (i as f64 + 0.5) * self.x_displacement
-            + (j as f64 + 0.5) * self.y_displacement;
+    // only affects perspective cameras
+    pub fn with_depth_of_field(mut self, aperture_radius: f64, focus_distance: f64) -> Self {
+        self.aperture_radius = aperture_radius;
+        self.focus_distance = focus_distance;
+        self
+    }
 
+    pub fn with_aperture_shape(mut self, aperture_shape: ApertureShape) -> Self {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+
+    pub fn with_clip_planes(mut self, near: f64, far: f64) -> Self {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    // rotate the camera around its view direction, counter clockwise as seen by the camera
+    pub fn with_roll(mut self, roll: f64) -> Self {
+        let (sin, cos) = roll.sin_cos();
+        let (u, v) = (self.u, self.v);
+        self.u = cos * u + sin * v;
+        self.v = cos * v - sin * u;
+        self
+    }
+
+    pub fn camera_to_world(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        direction.x * self.u + direction.y * self.v + direction.z * self.w
+    }
+
+    // world to camera coordinates, the inverse of camera_to_world after moving the camera to the
+    // origin
+    pub fn view_matrix(&self) -> Matrix4<f64> {
+        let rotation =
+            Matrix3::from_rows(&[self.u.transpose(), self.v.transpose(), self.w.transpose()]);
+        let translation = -(rotation * self.position);
+        rotation.to_homogeneous().append_translation(&translation)
+    }
+
+    // camera to clip coordinates for the rasterizer. the image covers the same region as the
+    // rays of the camera and the depth from near to far is mapped to [-1, 1]
+    pub fn projection_matrix(&self) -> Matrix4<f64> {
+        let image_x = -self.screen_origin.x;
+        let image_y = self.screen_origin.y;
         match self.kind {
             CameraKind::ORTHOGRAPHIC => {
-                let origin = self.position + Vector3::new(screen_point.x, screen_point.y, 0.);
-                let direction = Vector3::new(0., 0., -1.);
-                Ray::new(origin, direction)
+                Orthographic3::new(-image_x, image_x, -image_y, image_y, self.near, self.far)
+                    .to_homogeneous()
             }
             CameraKind::PERSPECTIVE => {
-                let origin = self.position;
-                let direction = (screen_point - self.position).normalize();
-                Ray::new(origin, direction)
+                let fov_y = 2. * (image_y / self.focal_length).atan();
+                Perspective3::new(image_x / image_y, fov_y, self.near, self.far).to_homogeneous()
             }
         }
     }
-}
 
-pub struct Light {
-    pub position: Vector3<f64>,
-    pub color: Vector3<f64>,
-}
+    // ray through the center of pixel (i, j) and the center of the lens
+    pub fn ray(&self, i: usize, j: usize) -> Ray {
+        self.lens_ray(i as f64 + 0.5, j as f64 + 0.5, &Vector2::zeros())
+    }
 
-impl Light {
-    pub fn new(position: Vector3<f64>, color: Vector3<f64>) -> Self {
-        Self { position, color }
+    // ray through the point (x, y) of the image, in pixels, and the point of the aperture
+    // that u in the unit square maps to
+    pub fn sample_ray(&self, x: f64, y: f64, u: &Vector2<f64>) -> Ray {
+        let lens_point = match self.aperture_shape {
+            ApertureShape::Disk => sampling::sample_concentric_disk(u),
+            ApertureShape::Polygon { blades, rotation } => {
+                sampling::sample_polygon(u, blades, rotation)
+            }
+        };
+        self.lens_ray(x, y, &lens_point)
+    }
+
+    // lens_point is on the unit aperture, which is scaled by the aperture radius
+    fn lens_ray(&self, x: f64, y: f64, lens_point: &Vector2<f64>) -> Ray {
+        let screen_point = self.screen_origin + x * self.x_displacement + y * self.y_displacement;
+
+        match self.kind {
+            CameraKind::ORTHOGRAPHIC => {
+                let origin = self.position
+                    + self.camera_to_world(&Vector3::new(screen_point.x, screen_point.y, 0.));
+                let direction = -self.w;
+                Ray::new(origin, direction)
+            }
+            CameraKind::PERSPECTIVE => {
+                let direction = self.camera_to_world(&screen_point).normalize();
+                if self.aperture_radius <= 0. {
+                    return Ray::new(self.position, direction);
+                }
+
+                // every ray through the pixel meets the pinhole ray on the plane of focus
+                let focus_point =
+                    self.position + direction * (self.focus_distance / -direction.dot(&self.w));
+                let lens_offset = self.aperture_radius * lens_point;
+                let origin = self.position + lens_offset.x * self.u + lens_offset.y * self.v;
+                Ray::new(origin, (focus_point - origin).normalize())
+            }
+        }
     }
 }
 
-#[derive(Debug)]
+// a ray only reports hits with t_min < t < t_max
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Vector3<f64>,
     pub direction: Vector3<f64>,
+    pub t_min: f64,
+    pub t_max: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vector3<f64>, direction: Vector3<f64>) -> Self {
-        Self { origin, direction }
+        Self::with_interval(origin, direction, 0., f64::INFINITY)
+    }
+
+    pub fn with_interval(
+        origin: Vector3<f64>,
+        direction: Vector3<f64>,
+        t_min: f64,
+        t_max: f64,
+    ) -> Self {
+        Self {
+            origin,
+            direction,
+            t_min,
+            t_max,
+        }
+    }
+
+    pub fn at(&self, t: f64) -> Vector3<f64> {
+        self.origin + t * self.direction
+    }
+
+    pub fn contains(&self, t: f64) -> bool {
+        t > self.t_min && t < self.t_max
+    }
+}
+
+// bound on the relative error of n chained floating point operations
+pub fn gamma(n: u32) -> f64 {
+    let eps = f64::EPSILON / 2.;
+    n as f64 * eps / (1. - n as f64 * eps)
+}
+
+// move a point on a surface along the normal just far enough that it is guaranteed to lie on the
+// side the direction points to, given the absolute error of the point in every dimension.
+// rays spawned from the result can not hit the surface they start on
+pub fn offset_ray_origin(
+    point: &Vector3<f64>,
+    error: &Vector3<f64>,
+    normal: &Vector3<f64>,
+    direction: &Vector3<f64>,
+) -> Vector3<f64> {
+    let distance = normal.abs().dot(error);
+    let mut offset = distance * normal;
+    if direction.dot(normal) < 0. {
+        offset = -offset;
+    }
+
+    // round away from the point so that the offset is not lost to rounding
+    let mut origin = point + offset;
+    for i in 0..3 {
+        if offset[i] > 0. {
+            origin[i] = origin[i].next_up();
+        } else if offset[i] < 0. {
+            origin[i] = origin[i].next_down();
+        }
     }
+    origin
 }
 pub struct Sphere {
     pub center: Vector3<f64>,
@@ -113,14 +313,22 @@ impl Object for Sphere {
         } else {
             let t1 = (-b + discriminant.sqrt()) / (2. * a);
             let t2 = (-b - discriminant.sqrt()) / (2. * a);
-            let t = if t1 < t2 { t1 } else { t2 };
-            if t > 0. {
-                let point = ray.origin + t * ray.direction;
-                Some(HitPoint {
+            // the near root is outside the interval when the ray starts inside the sphere
+            let (near, far) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+            let t = if ray.contains(near) { near } else { far };
+            if ray.contains(t) {
+                // project the point back onto the sphere, which bounds its error
+                let offset = ray.at(t) - self.center;
+                let offset = offset * (self.radius / offset.norm());
+                let point = self.center + offset;
+                let error = gamma(5) * (offset.abs() + self.center.abs());
+                Some(HitPoint::new(
                     t,
                     point,
-                    normal: self.normal(&point),
-                })
+                    self.normal(&point),
+                    error,
+                    &ray.direction,
+                ))
             } else {
                 None
             }
@@ -130,26 +338,214 @@ impl Object for Sphere {
     fn normal(&self, point: &Vector3<f64>) -> Vector3<f64> {
         (point - self.center).normalize()
     }
+
+    fn bounds(&self) -> AlignedBox3d {
+        let extent = Vector3::new(self.radius, self.radius, self.radius);
+        AlignedBox3d::new(self.center - extent, self.center + extent)
+    }
+
+    fn area(&self) -> f64 {
+        4. * PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, u: &Vector2<f64>) -> Option<SurfaceSample> {
+        let normal = sampling::sample_uniform_sphere(u);
+        let offset = self.radius * normal;
+        Some(SurfaceSample {
+            point: self.center + offset,
+            normal,
+            error: gamma(5) * (offset.abs() + self.center.abs()),
+        })
+    }
+}
+
+// a circle of the given radius around the center, facing towards the normal
+pub struct Disk {
+    pub center: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl Disk {
+    pub fn new(center: Vector3<f64>, normal: Vector3<f64>, radius: f64) -> Self {
+        Self {
+            center,
+            normal: normal.normalize(),
+            radius,
+        }
+    }
+}
+
+impl Object for Disk {
+    fn intersects(&self, ray: &Ray) -> Option<HitPoint> {
+        let denominator = self.normal.dot(&ray.direction);
+        if denominator == 0. {
+            return None;
+        }
+        let t = self.normal.dot(&(self.center - ray.origin)) / denominator;
+        if !ray.contains(t) {
+            return None;
+        }
+        // project the point back onto the plane of the disk
+        let offset = ray.at(t) - self.center;
+        let offset = offset - self.normal.dot(&offset) * self.normal;
+        if offset.norm_squared() > self.radius * self.radius {
+            return None;
+        }
+        let point = self.center + offset;
+        let error = gamma(5) * (offset.abs() + self.center.abs());
+        Some(HitPoint::new(t, point, self.normal, error, &ray.direction))
+    }
+
+    fn normal(&self, _point: &Vector3<f64>) -> Vector3<f64> {
+        self.normal
+    }
+
+    fn bounds(&self) -> AlignedBox3d {
+        // extent of the circle along every axis
+        let extent = self
+            .normal
+            .map(|n| self.radius * (1. - n * n).max(0.).sqrt());
+        AlignedBox3d::new(self.center - extent, self.center + extent)
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    fn sample_surface(&self, u: &Vector2<f64>) -> Option<SurfaceSample> {
+        let (tangent, bitangent) = utils::coordinate_system(&self.normal);
+        let d = self.radius * sampling::sample_concentric_disk(u);
+        let offset = d.x * tangent + d.y * bitangent;
+        Some(SurfaceSample {
+            point: self.center + offset,
+            normal: self.normal,
+            error: gamma(5) * (offset.abs() + self.center.abs()),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct HitPoint {
     pub t: f64,
     pub point: Vector3<f64>,
+    // geometric normal, always faces against the incoming ray
+    pub normal: Vector3<f64>,
+    // normal used for shading, interpolated across smooth meshes. it is on the same side of the
+    // surface as normal but may point slightly away from the ray
+    pub shading_normal: Vector3<f64>,
+    // whether the ray hit the outside of the surface, i.e. it is entering a closed object
+    pub front_face: bool,
+    // absolute floating point error of the point in every dimension
+    pub error: Vector3<f64>,
+    // barycentric coordinates of the point for triangles, zero for other shapes
+    pub barycentric: Vector3<f64>,
+}
+
+impl HitPoint {
+    // orients the outward normal of the surface against the ray direction
+    pub fn new(
+        t: f64,
+        point: Vector3<f64>,
+        outward_normal: Vector3<f64>,
+        error: Vector3<f64>,
+        direction: &Vector3<f64>,
+    ) -> Self {
+        let front_face = outward_normal.dot(direction) < 0.;
+        let normal = if front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        Self {
+            t,
+            point,
+            normal,
+            shading_normal: normal,
+            front_face,
+            error,
+            barycentric: Vector3::zeros(),
+        }
+    }
+
+    // the shading normal is flipped to the side of the geometric normal
+    pub fn with_shading_normal(mut self, shading_normal: Vector3<f64>) -> Self {
+        self.shading_normal = if shading_normal.dot(&self.normal) < 0. {
+            -shading_normal
+        } else {
+            shading_normal
+        };
+        self
+    }
+
+    // ray leaving the surface in the given direction
+    pub fn spawn_ray(&self, direction: Vector3<f64>) -> Ray {
+        let origin = offset_ray_origin(&self.point, &self.error, &self.normal, &direction);
+        Ray::new(origin, direction)
+    }
+
+    // ray leaving the surface towards the target, which it stops right before
+    pub fn spawn_ray_to(&self, target: &Vector3<f64>) -> Ray {
+        let origin = offset_ray_origin(
+            &self.point,
+            &self.error,
+            &self.normal,
+            &(target - self.point),
+        );
+        let to_target = target - origin;
+        let distance = to_target.norm();
+        Ray::with_interval(origin, to_target / distance, 0., distance * (1. - gamma(3)))
+    }
+}
+
+// uniformly distributed point on the surface of an object
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub point: Vector3<f64>,
+    // outward normal
     pub normal: Vector3<f64>,
+    pub error: Vector3<f64>,
 }
 
-pub trait Object {
+// objects are shared between the render threads
+pub trait Object: Send + Sync {
     fn intersects(&self, ray: &Ray) -> Option<HitPoint>;
     fn normal(&self, point: &Vector3<f64>) -> Vector3<f64>;
+    // axis aligned box enclosing the object, used to build the scene bvh
+    fn bounds(&self) -> AlignedBox3d;
+
+    // whether the object blocks the ray before t_max, any hit will do so implementations
+    // can stop early instead of searching for the closest one
+    fn occluded(&self, ray: &Ray, t_max: f64) -> bool {
+        let ray = Ray {
+            t_max: ray.t_max.min(t_max),
+            ..*ray
+        };
+        self.intersects(&ray).is_some()
+    }
+
+    // surface area, zero for objects that do not support sampling
+    fn area(&self) -> f64 {
+        0.
+    }
+
+    // uniform point on the surface, needed for emissive objects to act as area lights
+    fn sample_surface(&self, _u: &Vector2<f64>) -> Option<SurfaceSample> {
+        None
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct Material {
     pub diffuse_color: Vector3<f64>,
     pub specular_color: Vector3<f64>,
     pub specular_exponent: f64,
     pub reflection_color: Vector3<f64>,
     pub refraction_color: Vector3<f64>,
+    // index of refraction of the medium inside the object, only used when refraction_color is not black
+    pub refractive_index: f64,
+    // radiance emitted from the front side of the surface
+    pub emission: Vector3<f64>,
 }
 
 impl Material {
@@ -166,8 +562,28 @@ impl Material {
             specular_exponent,
             reflection_color,
             refraction_color,
+            refractive_index: 1.,
+            emission: Vector3::zeros(),
         }
     }
+
+    pub fn with_refractive_index(mut self, refractive_index: f64) -> Self {
+        self.refractive_index = refractive_index;
+        self
+    }
+
+    pub fn with_emission(mut self, emission: Vector3<f64>) -> Self {
+        self.emission = emission;
+        self
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emission != Vector3::zeros()
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.refraction_color != Vector3::zeros()
+    }
 }
 
 pub struct Parallelogram {
@@ -194,19 +610,26 @@ impl Object for Parallelogram {
             self.point1 - self.point3,
             ray.direction,
         ]);
-        let uvt = asystem.lu().solve(&bystem).unwrap();
-        let t = uvt[2];
+        // rays parallel to the plane give a singular system
+        let uvt = asystem.lu().solve(&bystem)?;
+        let (u, v, t) = (uvt[0], uvt[1], uvt[2]);
 
-        if t < 1e-6 {
-            // if t < 0. {
+        // the hit has to be inside the parallelogram for its bounds to be valid
+        if !(0. ..=1.).contains(&u) || !(0. ..=1.).contains(&v) || !ray.contains(t) {
             None
         } else {
-            let point = ray.origin + t * ray.direction;
-            let mut normal = self.normal(&point);
-            if normal.dot(&ray.direction) > 0. {
-                normal = -normal;
-            }
-            Some(HitPoint { t, point, normal })
+            // reconstructing the point from the parametric coordinates keeps it on the plane
+            let edge1 = u * (self.point2 - self.point1);
+            let edge2 = v * (self.point3 - self.point1);
+            let point = self.point1 + edge1 + edge2;
+            let error = gamma(7) * (self.point1.abs() + edge1.abs() + edge2.abs());
+            Some(HitPoint::new(
+                t,
+                point,
+                self.normal(&point),
+                error,
+                &ray.direction,
+            ))
         }
     }
 
@@ -215,6 +638,33 @@ impl Object for Parallelogram {
             .cross(&(self.point3 - self.point1))
             .normalize()
     }
+
+    fn bounds(&self) -> AlignedBox3d {
+        let mut bbox = AlignedBox3d::default();
+        bbox.extend(self.point1);
+        bbox.extend(self.point2);
+        bbox.extend(self.point3);
+        // the fourth corner, opposite of point1
+        bbox.extend(self.point2 + self.point3 - self.point1);
+        bbox
+    }
+
+    fn area(&self) -> f64 {
+        (self.point2 - self.point1)
+            .cross(&(self.point3 - self.point1))
+            .norm()
+    }
+
+    fn sample_surface(&self, u: &Vector2<f64>) -> Option<SurfaceSample> {
+        let edge1 = u.x * (self.point2 - self.point1);
+        let edge2 = u.y * (self.point3 - self.point1);
+        let point = self.point1 + edge1 + edge2;
+        Some(SurfaceSample {
+            point,
+            normal: self.normal(&point),
+            error: gamma(7) * (self.point1.abs() + edge1.abs() + edge2.abs()),
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -242,6 +692,57 @@ impl Triangle {
             .cross(&(self.point3 - self.point1))
             .normalize()
     }
+
+    // barycentric coordinates of the point of the triangle closest to the given point, found by
+    // checking in which vertex, edge or face region of the triangle it lies
+    pub fn closest_barycentric(&self, point: &Vector3<f64>) -> Vector3<f64> {
+        let ab = self.point2 - self.point1;
+        let ac = self.point3 - self.point1;
+        let ap = point - self.point1;
+        let d1 = ab.dot(&ap);
+        let d2 = ac.dot(&ap);
+        if d1 <= 0. && d2 <= 0. {
+            return Vector3::new(1., 0., 0.);
+        }
+
+        let bp = point - self.point2;
+        let d3 = ab.dot(&bp);
+        let d4 = ac.dot(&bp);
+        if d3 >= 0. && d4 <= d3 {
+            return Vector3::new(0., 1., 0.);
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0. && d1 >= 0. && d3 <= 0. {
+            let v = d1 / (d1 - d3);
+            return Vector3::new(1. - v, v, 0.);
+        }
+
+        let cp = point - self.point3;
+        let d5 = ab.dot(&cp);
+        let d6 = ac.dot(&cp);
+        if d6 >= 0. && d5 <= d6 {
+            return Vector3::new(0., 0., 1.);
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0. && d2 >= 0. && d6 <= 0. {
+            let w = d2 / (d2 - d6);
+            return Vector3::new(1. - w, 0., w);
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0. && d4 - d3 >= 0. && d5 - d6 >= 0. {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            return Vector3::new(0., 1. - w, w);
+        }
+
+        // inside the face
+        let denominator = va + vb + vc;
+        let v = vb / denominator;
+        let w = vc / denominator;
+        Vector3::new(1. - v - w, v, w)
+    }
 }
 
 impl Object for Triangle {
@@ -259,16 +760,19 @@ impl Object for Triangle {
                 let v = uvt[1];
                 let t = uvt[2];
 
-                if u < 0. || v < 0. || u + v >= 1. || t < 1e-6 {
+                if u < 0. || v < 0. || u + v >= 1. || !ray.contains(t) {
                     None
                 } else {
-                    let point = ray.origin + t * ray.direction;
-                    // let mut normal = self.normal(&point);
-                    let mut normal = Object::normal(self, &point); //this is required because we wan't to have a default implementation of the normal function in the base struct 
-                    if normal.dot(&ray.direction) > 0. {
-                        normal = -normal;
-                    }
-                    Some(HitPoint { t, point, normal })
+                    // reconstructing the point from the barycentric coordinates keeps it on the plane
+                    let weighted = [(1. - u - v) * self.point1, u * self.point2, v * self.point3];
+                    let point = weighted[0] + weighted[1] + weighted[2];
+                    let error =
+                        gamma(7) * (weighted[0].abs() + weighted[1].abs() + weighted[2].abs());
+                    // let normal = self.normal(&point);
+                    let normal = Object::normal(self, &point); //this is required because we wan't to have a default implementation of the normal function in the base struct
+                    let mut hit_point = HitPoint::new(t, point, normal, error, &ray.direction);
+                    hit_point.barycentric = Vector3::new(1. - u - v, u, v);
+                    Some(hit_point)
                 }
             }
             None => None,
@@ -278,24 +782,110 @@ impl Object for Triangle {
     fn normal(&self, _point: &Vector3<f64>) -> Vector3<f64> {
         self.normal()
     }
+
+    fn bounds(&self) -> AlignedBox3d {
+        AlignedBox3d::from_triangle(self)
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * (self.point2 - self.point1)
+            .cross(&(self.point3 - self.point1))
+            .norm()
+    }
+
+    fn sample_surface(&self, u: &Vector2<f64>) -> Option<SurfaceSample> {
+        let barycentric = sampling::sample_uniform_triangle(u);
+        let weighted = [
+            barycentric.x * self.point1,
+            barycentric.y * self.point2,
+            barycentric.z * self.point3,
+        ];
+        Some(SurfaceSample {
+            point: weighted[0] + weighted[1] + weighted[2],
+            normal: self.normal(),
+            error: gamma(6) * (weighted[0].abs() + weighted[1].abs() + weighted[2].abs()),
+        })
+    }
+}
+
+// index into the material table of a scene
+pub type MaterialId = usize;
+
+// a shape together with the material it is rendered with
+pub struct SceneObject {
+    pub shape: Box<dyn Object>,
+    pub material: MaterialId,
+}
+
+impl SceneObject {
+    pub fn new(shape: Box<dyn Object>, material: MaterialId) -> Self {
+        Self { shape, material }
+    }
 }
 
 pub struct Scene {
-    pub objects: Vec<Box<dyn Object>>,
+    pub objects: Vec<SceneObject>,
+    pub materials: Vec<Material>,
+    // Scene::new appends an area light for every emissive object
     pub lights: Vec<Light>,
     pub ambient_color: Vector3<f64>,
+    // seen by rays that leave the scene, None leaves the image transparent there
+    pub background: Option<Background>,
+    // top level acceleration structure over the objects, the bvh of a mesh acts as the bottom level.
+    // it is built by Scene::new and has to be rebuilt if the objects change
+    pub bvh: BVH,
 }
 
 impl Scene {
     pub fn new(
-        objects: Vec<Box<dyn Object>>,
-        lights: Vec<Light>,
+        objects: Vec<SceneObject>,
+        materials: Vec<Material>,
+        mut lights: Vec<Light>,
         ambient_color: Vector3<f64>,
     ) -> Self {
+        for object in &objects {
+            assert!(
+                object.material < materials.len(),
+                "object refers to material {} but the scene only has {} materials",
+                object.material,
+                materials.len()
+            );
+        }
+
+        // emissive objects light the scene like any other light
+        for (idx, object) in objects.iter().enumerate() {
+            if materials[object.material].is_emissive() && object.shape.area() > 0. {
+                lights.push(Light::Area { object: idx });
+            }
+        }
+
+        let bvh = Self::build_bvh(&objects);
         Self {
             objects,
+            materials,
             lights,
             ambient_color,
+            background: None,
+            bvh,
         }
     }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = Some(background);
+        self
+    }
+
+    fn build_bvh(objects: &[SceneObject]) -> BVH {
+        let bounds: Vec<AlignedBox3d> =
+            objects.iter().map(|object| object.shape.bounds()).collect();
+        BVH::build(&bounds, &BvhBuildOptions::default())
+    }
+
+    pub fn rebuild_bvh(&mut self) {
+        self.bvh = Self::build_bvh(&self.objects);
+    }
+
+    pub fn material(&self, id: MaterialId) -> &Material {
+        &self.materials[id]
+    }
 }
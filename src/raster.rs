@@ -1,10 +1,7 @@
-use crate::{
-    composites,
-    primitives::{self, Camera, Light},
-};
-use nalgebra::{DMatrix, Matrix3, Vector3, Vector4, Matrix4};
+use crate::{composites, datastructures::AlignedBox3d, lights::Light, primitives::Camera};
+use nalgebra::{DMatrix, Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4};
+use std::marker::PhantomData;
 #[macro_export]
-
 macro_rules! print_matrix_row_major {
     ($var_name:expr, $matrix:expr) => {
         println!(
@@ -29,304 +26,935 @@ macro_rules! print_matrix_row_major {
     };
 }
 
-pub fn rasterize(
+// which triangles are skipped before any fragment is shaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+// the order in which the corners of a front facing triangle appear on screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+// what is drawn of a triangle: its inside, its edges or its corners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineAlgorithm {
+    // aliased, a pixel is either part of the line or not
+    Bresenham,
+    // xiaolin wu's anti-aliased lines, pixels are blended by how much of them the line covers
+    Wu,
+}
+
+// fixed function settings of the pipeline. by default nothing is culled, so meshes are drawn
+// whatever the winding of their triangles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipelineState {
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub polygon_mode: PolygonMode,
+    pub line_algorithm: LineAlgorithm,
+    // in pixels
+    pub line_width: f64,
+    // side of the square drawn for every point, in pixels
+    pub point_size: f64,
+    // added to the depth of every fragment, the slope bias is scaled by the largest change in
+    // depth per pixel of the triangle. negative values move fragments towards the camera so a
+    // wireframe stays visible over its filled mesh
+    pub depth_bias: f64,
+    pub slope_depth_bias: f64,
+}
+
+impl Default for PipelineState {
+    fn default() -> Self {
+        Self {
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            polygon_mode: PolygonMode::Fill,
+            line_algorithm: LineAlgorithm::Bresenham,
+            line_width: 1.,
+            point_size: 1.,
+            depth_bias: 0.,
+            slope_depth_bias: 0.,
+        }
+    }
+}
+
+impl PipelineState {
+    // edges drawn over the same mesh drawn filled before, sharing its depth buffer
+    pub fn wireframe_overlay() -> Self {
+        Self::default()
+            .with_polygon_mode(PolygonMode::Line)
+            .with_depth_bias(-1e-6, -1.)
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_front_face(mut self, front_face: FrontFace) -> Self {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn with_lines(mut self, line_algorithm: LineAlgorithm, line_width: f64) -> Self {
+        self.line_algorithm = line_algorithm;
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn with_point_size(mut self, point_size: f64) -> Self {
+        self.point_size = point_size;
+        self
+    }
+
+    pub fn with_depth_bias(mut self, depth_bias: f64, slope_depth_bias: f64) -> Self {
+        self.depth_bias = depth_bias;
+        self.slope_depth_bias = slope_depth_bias;
+        self
+    }
+
+    // whether a polygon with the given signed area, positive for counter clockwise on screen,
+    // faces the camera and whether it is drawn
+    fn facing(&self, signed_area: f64) -> (bool, bool) {
+        let front_facing = match self.front_face {
+            FrontFace::CounterClockwise => signed_area > 0.,
+            FrontFace::Clockwise => signed_area < 0.,
+        };
+        let culled = match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Front => front_facing,
+            CullMode::Back => !front_facing,
+        };
+        (front_facing, !culled && signed_area != 0.)
+    }
+}
+
+// depths go from -1 at the near plane to 1 at the far plane, smaller is closer. draw calls that
+// share a depth buffer hide each other
+pub type DepthBuffer = DMatrix<f64>;
+
+// depth buffer of the size of the frame buffer that lets everything through, also for drawing on
+// top of a ray traced image
+pub fn new_depth_buffer(frame_buffer: &DMatrix<Vector4<f64>>) -> DepthBuffer {
+    DMatrix::from_element(frame_buffer.nrows(), frame_buffer.ncols(), f64::INFINITY)
+}
+
+// draw the triangles of a mesh with the program, the attributes of every vertex are taken
+// from the mesh
+pub fn rasterize<P: Shader>(
     mesh: &composites::Mesh,
-    uniform: Uniform,
-    program: Program,
+    uniform: &P::Uniform,
+    program: &P,
+    state: &PipelineState,
+    frame_buffer: &mut DMatrix<Vector4<f64>>,
+    depth_buffer: &mut DepthBuffer,
+) where
+    P::Attributes: MeshAttributes,
+{
+    let vertices: Vec<P::Attributes> = (0..mesh.positions.len())
+        .map(|idx| P::Attributes::from_mesh(mesh, idx))
+        .collect();
+    let mut rasterizer = Rasterizer {
+        uniform,
+        program,
+        state,
+        frame_buffer,
+        depth_buffer,
+    };
+    rasterizer.draw_triangles(&vertices, &mesh.indices);
+}
+
+// draw the triangles given by three indices into the vertices each
+pub fn draw_triangles<P: Shader>(
+    vertices: &[P::Attributes],
+    indices: &[[u32; 3]],
+    uniform: &P::Uniform,
+    program: &P,
+    state: &PipelineState,
+    frame_buffer: &mut DMatrix<Vector4<f64>>,
+    depth_buffer: &mut DepthBuffer,
+) {
+    let mut rasterizer = Rasterizer {
+        uniform,
+        program,
+        state,
+        frame_buffer,
+        depth_buffer,
+    };
+    rasterizer.draw_triangles(vertices, indices);
+}
+
+// draw the lines between two vertices each, with the line algorithm and width of the state
+pub fn draw_lines<P: Shader>(
+    vertices: &[P::Attributes],
+    indices: &[[u32; 2]],
+    uniform: &P::Uniform,
+    program: &P,
+    state: &PipelineState,
+    frame_buffer: &mut DMatrix<Vector4<f64>>,
+    depth_buffer: &mut DepthBuffer,
+) {
+    let mut rasterizer = Rasterizer {
+        uniform,
+        program,
+        state,
+        frame_buffer,
+        depth_buffer,
+    };
+    let shaded = rasterizer.shade_vertices(vertices);
+    for line in indices {
+        let [a, b] = line.map(|idx| &shaded[idx as usize]);
+        rasterizer.draw_line(a, b, true, state.depth_bias);
+    }
+}
+
+// draw every vertex as a square of the point size of the state
+pub fn draw_points<P: Shader>(
+    vertices: &[P::Attributes],
+    uniform: &P::Uniform,
+    program: &P,
+    state: &PipelineState,
+    frame_buffer: &mut DMatrix<Vector4<f64>>,
+    depth_buffer: &mut DepthBuffer,
+) {
+    let mut rasterizer = Rasterizer {
+        uniform,
+        program,
+        state,
+        frame_buffer,
+        depth_buffer,
+    };
+    for vertex in rasterizer.shade_vertices(vertices) {
+        rasterizer.draw_point(&vertex, true, state.depth_bias);
+    }
+}
+
+// the edges of the boxes in a single color, for example the nodes of a bvh over a render
+pub fn draw_boxes(
+    boxes: &[AlignedBox3d],
+    color: Vector3<f64>,
+    uniform: &Uniform,
+    state: &PipelineState,
     frame_buffer: &mut DMatrix<Vector4<f64>>,
+    depth_buffer: &mut DepthBuffer,
 ) {
-    // init the meta triangles
-    // using literal construction is more verbose than implementing the new constructor
-    // let frame_buffer = &mut camera.image;
-    let mut z_buffer = DMatrix::from_element(
-        frame_buffer.nrows(),
-        frame_buffer.ncols(),
-        f64::NEG_INFINITY,
+    // corners are numbered by the bits of x, y and z being at the max of the box
+    const EDGES: [[u32; 2]; 12] = [
+        [0, 1],
+        [2, 3],
+        [4, 5],
+        [6, 7],
+        [0, 2],
+        [1, 3],
+        [4, 6],
+        [5, 7],
+        [0, 4],
+        [1, 5],
+        [2, 6],
+        [3, 7],
+    ];
+    let mut vertices = Vec::with_capacity(8 * boxes.len());
+    let mut indices = Vec::with_capacity(12 * boxes.len());
+    for bbox in boxes {
+        let first = vertices.len() as u32;
+        vertices.extend(bbox.corners().map(|position| Vertex {
+            position,
+            normal: Vector3::zeros(),
+        }));
+        indices.extend(EDGES.map(|edge| edge.map(|corner| first + corner)));
+    }
+    let program = SolidColor { color };
+    draw_lines(
+        &vertices,
+        &indices,
+        uniform,
+        &program,
+        state,
+        frame_buffer,
+        depth_buffer,
     );
-    let mut triangles: Vec<ShaderTriangle> = Vec::new();
-    for (i, triangle) in mesh.triangles.iter().enumerate() {
-        let normal = triangle.normal();
-        let v0 = Vertex {
-            position: triangle.point1,
-            normal: normal,
-        };
-        let v1 = Vertex {
-            position: triangle.point2,
-            normal: normal,
-        };
-        let v2 = Vertex {
-            position: triangle.point3,
-            normal: normal,
-        };
+}
+
+// state of a draw call
+struct Rasterizer<'a, P: Shader> {
+    uniform: &'a P::Uniform,
+    program: &'a P,
+    state: &'a PipelineState,
+    frame_buffer: &'a mut DMatrix<Vector4<f64>>,
+    depth_buffer: &'a mut DepthBuffer,
+}
 
-        triangles.push(ShaderTriangle {
-            v0: v0,
-            v1: v1,
-            v2: v2,
-        });
+impl<P: Shader> Rasterizer<'_, P> {
+    // run the vertex shader once per vertex, it moves the vertices to clip space
+    fn shade_vertices(&self, vertices: &[P::Attributes]) -> Vec<(Vector4<f64>, P::Varyings)> {
+        vertices
+            .iter()
+            .map(|vertex| self.program.vertex(vertex, self.uniform))
+            .collect()
     }
 
-    // run the vertex shader, modify the vertices of the triangles
-    for triangle in &mut triangles {
-        triangle.v0 = vertex_shader(&triangle.v0, &uniform);
-        triangle.v1 = vertex_shader(&triangle.v1, &uniform);
-        triangle.v2 = vertex_shader(&triangle.v2, &uniform);
+    // perspective divide and viewport transform to pixels, the first row is the top one.
+    // like gl_FragCoord the w component keeps 1 / w for perspective correct interpolation
+    fn to_window(&self, clip_position: &Vector4<f64>) -> Vector4<f64> {
+        let (width, height) = (
+            self.frame_buffer.nrows() as f64,
+            self.frame_buffer.ncols() as f64,
+        );
+        let ndc = clip_position.xyz() / clip_position.w;
+        Vector4::new(
+            (ndc.x + 1.) / 2. * width,
+            (1. - ndc.y) / 2. * height,
+            ndc.z,
+            1. / clip_position.w,
+        )
+    }
+
+    fn draw_triangles(&mut self, vertices: &[P::Attributes], indices: &[[u32; 3]]) {
+        let shaded = self.shade_vertices(vertices);
+        for face in indices {
+            let corners = face.map(|idx| &shaded[idx as usize]);
+
+            // cut away everything outside the view volume, so vertices behind the camera never
+            // get divided by a negative w
+            let polygon = clip_polygon(corners.map(|corner| corner.clone()).to_vec());
+            if polygon.len() < 3 {
+                continue;
+            }
+            let window: Vec<Vector4<f64>> = polygon
+                .iter()
+                .map(|(clip_position, _)| self.to_window(clip_position))
+                .collect();
+
+            // shoelace formula, the window y axis points down so the sign is flipped to make
+            // counter clockwise on screen positive
+            let signed_area = -0.5
+                * (0..window.len())
+                    .map(|k| {
+                        let (a, b) = (window[k], window[(k + 1) % window.len()]);
+                        a.x * b.y - b.x * a.y
+                    })
+                    .sum::<f64>();
+            let (front_facing, visible) = self.state.facing(signed_area);
+            if !visible {
+                continue;
+            }
+            let bias = self.state.depth_bias
+                + self.state.slope_depth_bias * depth_slope([window[0], window[1], window[2]]);
+
+            match self.state.polygon_mode {
+                // the clipped polygon is convex, split it into a triangle fan
+                PolygonMode::Fill => {
+                    for k in 1..polygon.len() - 1 {
+                        let corners = [0, k, k + 1];
+                        self.fill_triangle(
+                            corners.map(|c| window[c]),
+                            corners.map(|c| &polygon[c].1),
+                            front_facing,
+                            bias,
+                        );
+                    }
+                }
+                // the edges of the triangle, not the ones the clipping added
+                PolygonMode::Line => {
+                    for k in 0..3 {
+                        self.draw_line(corners[k], corners[(k + 1) % 3], front_facing, bias);
+                    }
+                }
+                PolygonMode::Point => {
+                    for corner in corners {
+                        self.draw_point(corner, front_facing, bias);
+                    }
+                }
+            }
+        }
+    }
+
+    // run the fragment shader for a pixel and blend the result into the buffers. partly covered
+    // pixels keep some of their old color and their depth, so they do not hide what is drawn
+    // behind them later
+    fn shade(
+        &mut self,
+        (i, j): (i64, i64),
+        input: FragmentInput,
+        varyings: &P::Varyings,
+        coverage: f64,
+    ) {
+        if i < 0
+            || j < 0
+            || i >= self.frame_buffer.nrows() as i64
+            || j >= self.frame_buffer.ncols() as i64
+            || coverage <= 0.
+        {
+            return;
+        }
+        let (i, j) = (i as usize, j as usize);
+        let fragment = self.program.fragment(&input, varyings, self.uniform);
+        let old_color = self.frame_buffer[(i, j)];
+        let (color, z) = self
+            .program
+            .blend(&fragment, old_color, self.depth_buffer[(i, j)]);
+        if coverage >= 1. {
+            self.frame_buffer[(i, j)] = color;
+            self.depth_buffer[(i, j)] = z;
+        } else {
+            self.frame_buffer[(i, j)] = old_color.lerp(&color, coverage);
+        }
     }
 
     // rasterize the triangles by running the fragment shader on every pixel
     // for every triangle, find pixels that are inside the bounding box of the triangle
     // compute the barycentric coordinates of the pixel, its interpolated attributes
     // and run the fragment shader on it
-    for (i, triangle) in triangles.iter().enumerate() {
-        // Collect coordinates into a matrix and convert to canonical representation
-        let mut p = nalgebra::Matrix3::new(
-            triangle.v0.position.x,
-            triangle.v0.position.y,
-            triangle.v0.position.z,
-            triangle.v1.position.x,
-            triangle.v1.position.y,
-            triangle.v1.position.z,
-            triangle.v2.position.x,
-            triangle.v2.position.y,
-            triangle.v2.position.z,
-        );
-
-        // print_matrix_row_major!("<p", p);
-
-        // Coordinates are in -1..1, rescale to pixel size (x,y only)
-        // TODO row_mut gives wrong ans, column mut works, really confused about this notation!
-        p.column_mut(0)
-            .iter_mut()
-            .for_each(|x| *x = (*x + 1.) / 2. * frame_buffer.ncols() as f64);
-        p.column_mut(1)
-            .iter_mut()
-            .for_each(|y| *y = (*y + 1.) / 2. * frame_buffer.nrows() as f64);
+    fn fill_triangle(
+        &mut self,
+        window: [Vector4<f64>; 3],
+        varyings: [&P::Varyings; 3],
+        front_facing: bool,
+        bias: f64,
+    ) {
+        // window coordinates as rows, x and y in pixels
+        let p = Matrix3::from_rows(&[
+            window[0].xyz().transpose(),
+            window[1].xyz().transpose(),
+            window[2].xyz().transpose(),
+        ]);
+        let inverse_w = Vector3::new(window[0].w, window[1].w, window[2].w);
 
         let lx = p.column(0).min().floor() as usize;
         let ly = p.column(1).min().floor() as usize;
         let ux = p.column(0).max().ceil() as usize;
         let uy = p.column(1).max().ceil() as usize;
 
-        // clamp the bounding box to the frame buffer
-        let lx = lx.max(0).min(frame_buffer.ncols() - 1);
-        let ly = ly.max(0).min(frame_buffer.nrows() - 1);
-        let ux = ux.max(0).min(frame_buffer.ncols() - 1);
-        let uy = uy.max(0).min(frame_buffer.nrows() - 1);
-
-        // print_matrix_row_major!("p", p);
-        // println!("lx: {}, ly: {}, ux: {}, uy: {}", lx, ly, ux, uy);
+        // clamp the bounding box to the frame buffer, which has a row per x and a column per y
+        let lx = lx.min(self.frame_buffer.nrows() - 1);
+        let ly = ly.min(self.frame_buffer.ncols() - 1);
+        let ux = ux.min(self.frame_buffer.nrows() - 1);
+        let uy = uy.min(self.frame_buffer.ncols() - 1);
 
         // Build the implicit triangle representation from matrix p
         // and set the last row to 1
-        let mut matrix_A = p.transpose();
-        matrix_A[(2, 0)] = 1.;
-        matrix_A[(2, 1)] = 1.;
-        matrix_A[(2, 2)] = 1.;
-
-        let inv = matrix_A.try_inverse();
-        let matrix_A_inv = match inv {
-            Some(inv) => inv,
-            None => {
-                // for dragon two times this happens - 504336, 533340
-                continue;
-            }
-        };
-        // this is the same as using adjugate logic, differs from cpp eigen!!
+        let mut matrix_a = p.transpose();
+        matrix_a[(2, 0)] = 1.;
+        matrix_a[(2, 1)] = 1.;
+        matrix_a[(2, 2)] = 1.;
 
-        // print_matrix_row_major!("A", matrix_A);
-        // print_matrix_row_major!("Ai", matrix_A_inv);
+        // degenerate triangles cover no pixels
+        let Some(matrix_a_inv) = matrix_a.try_inverse() else {
+            return;
+        };
 
         for i in lx..=ux {
             for j in ly..=uy {
-                let pixel = nalgebra::Vector3::new(i as f64 + 0.5, j as f64 + 0.5, 1.);
-                let bary_coords = matrix_A_inv * pixel;
-                // println!("pixel: {:.4}, {:.4}, {:.4}", pixel.x, pixel.y, pixel.z);
-                // println!(
-                //     "i: {}, j: {}, b: {:.4}, {:.4}, {:.4}",
-                //     i, j, bary_coords[0], bary_coords[1], bary_coords[2]
-                // );
+                let pixel = Vector3::new(i as f64 + 0.5, j as f64 + 0.5, 1.);
+                let bary_coords = matrix_a_inv * pixel;
                 if bary_coords.min() >= 0. {
-                    let v = vertex_interpolation(&triangle, bary_coords);
-                    // println!(
-                    //     "va: {:.4}, {:.4}, {:.4}",
-                    //     v.position.x, v.position.y, v.position.z
-                    // );
-                    // only render in the biunit cube
-                    if v.position.z <= 1. {
-                        // if v.position.z >= -1. && v.position.z <= 1. {
-                        let fragment = fragment_shader(&v, &uniform);
-                        // let blend = blending_shader(&fragment, camera.image[(i, j)]);
-                        let (blend, new_z) =
-                            blending_shader(&fragment, frame_buffer[(i, j)], z_buffer[(i, j)]);
-                        frame_buffer[(i, j)] = blend;
-                        z_buffer[(i, j)] = new_z;
+                    // depth is linear in screen space, the varyings are linear in clip space so
+                    // they are weighted by 1 / w
+                    let weights = bary_coords.component_mul(&inverse_w);
+                    let input = FragmentInput {
+                        position: p.transpose() * bary_coords + Vector3::new(0., 0., bias),
+                        screen_barycentric: bary_coords,
+                        barycentric: weights / weights.sum(),
+                        front_facing,
+                        point_coord: Vector2::zeros(),
+                    };
+                    let v = P::Varyings::interpolate(varyings, &input.barycentric);
+                    self.shade((i as i64, j as i64), input, &v, 1.);
+                }
+            }
+        }
+    }
+
+    // the part of the line between two vertices in clip space that is inside the view volume
+    fn draw_line(
+        &mut self,
+        a: &(Vector4<f64>, P::Varyings),
+        b: &(Vector4<f64>, P::Varyings),
+        front_facing: bool,
+        bias: f64,
+    ) {
+        let Some([a, b]) = clip_line(a, b) else {
+            return;
+        };
+        let ends = [self.to_window(&a.0), self.to_window(&b.0)];
+        let direction = ends[1].xy() - ends[0].xy();
+        let length_squared = direction.norm_squared();
+        if length_squared == 0. {
+            return;
+        }
+
+        // the line is parametrized by the projection of the pixel center onto it
+        let plot = |rasterizer: &mut Self, (i, j): (i64, i64), coverage: f64| {
+            let center = Vector2::new(i as f64 + 0.5, j as f64 + 0.5);
+            let t = ((center - ends[0].xy()).dot(&direction) / length_squared).clamp(0., 1.);
+            let screen = Vector3::new(1. - t, t, 0.);
+            let weights = Vector3::new((1. - t) * ends[0].w, t * ends[1].w, 0.);
+            let input = FragmentInput {
+                position: Vector3::new(
+                    center.x,
+                    center.y,
+                    (1. - t) * ends[0].z + t * ends[1].z + bias,
+                ),
+                screen_barycentric: screen,
+                barycentric: weights / weights.sum(),
+                front_facing,
+                point_coord: Vector2::zeros(),
+            };
+            let v = P::Varyings::interpolate([&a.1, &b.1, &b.1], &input.barycentric);
+            rasterizer.shade((i, j), input, &v, coverage);
+        };
+
+        // work along the axis the line is longer in, the width is measured across the line so
+        // it covers more pixels along the other axis when the line is diagonal
+        let steep = direction.y.abs() > direction.x.abs();
+        let major_minor = |p: Vector2<f64>| if steep { (p.y, p.x) } else { (p.x, p.y) };
+        let pixel = |major: i64, minor: i64| {
+            if steep {
+                (minor, major)
+            } else {
+                (major, minor)
+            }
+        };
+        let (mut start, mut end) = (major_minor(ends[0].xy()), major_minor(ends[1].xy()));
+        if start.0 > end.0 {
+            std::mem::swap(&mut start, &mut end);
+        }
+        let gradient = (end.1 - start.1) / (end.0 - start.0);
+        let span = self.state.line_width * (1. + gradient * gradient).sqrt();
+
+        match self.state.line_algorithm {
+            LineAlgorithm::Bresenham => {
+                // integer steps through the pixels containing the end points, then every pixel
+                // is widened across the minor axis
+                let (mut major, mut minor) = (start.0.floor() as i64, start.1.floor() as i64);
+                let (major_end, minor_end) = (end.0.floor() as i64, end.1.floor() as i64);
+                let delta_major = major_end - major;
+                let delta_minor = (minor_end - minor).abs();
+                let step = if minor_end > minor { 1 } else { -1 };
+                let mut error = 2 * delta_minor - delta_major;
+                let count = span.round().max(1.) as i64;
+                loop {
+                    for k in 0..count {
+                        plot(self, pixel(major, minor + k - (count - 1) / 2), 1.);
+                    }
+                    if major == major_end {
+                        break;
+                    }
+                    if error > 0 {
+                        minor += step;
+                        error -= 2 * delta_major;
+                    }
+                    error += 2 * delta_minor;
+                    major += 1;
+                }
+            }
+            LineAlgorithm::Wu => {
+                // every column is covered by an interval of the minor axis, pixels get the part
+                // of them inside it. the end columns are weighted by how much of them the line
+                // spans, which is wu's gap at the end points
+                let half = span / 2.;
+                for major in start.0.floor() as i64..=end.0.floor() as i64 {
+                    let column = major as f64;
+                    let gap = (end.0.min(column + 1.) - start.0.max(column)).clamp(0., 1.);
+                    let middle = (column + 0.5).clamp(start.0, end.0);
+                    let center = start.1 + gradient * (middle - start.0);
+                    let (low, high) = (center - half, center + half);
+                    for minor in low.floor() as i64..=high.floor() as i64 {
+                        let row = minor as f64;
+                        let coverage = (high.min(row + 1.) - low.max(row)).clamp(0., 1.);
+                        plot(self, pixel(major, minor), coverage * gap);
                     }
                 }
             }
         }
+    }
 
-        // println!("value at 350 350 {:?}", frame_buffer[(350, 350)]);
+    // a square of the point size around a vertex inside the view volume
+    fn draw_point(&mut self, vertex: &(Vector4<f64>, P::Varyings), front_facing: bool, bias: f64) {
+        if clip_planes().iter().any(|plane| plane.dot(&vertex.0) < 0.) {
+            return;
+        }
+        let window = self.to_window(&vertex.0);
+        let size = self.state.point_size;
+        let (left, top) = (window.x - size / 2., window.y - size / 2.);
+        // the pixels whose centers are inside the square
+        let first = |low: f64| (low - 0.5).ceil() as i64;
+        for i in first(left)..first(left + size) {
+            for j in first(top)..first(top + size) {
+                let center = Vector2::new(i as f64 + 0.5, j as f64 + 0.5);
+                let input = FragmentInput {
+                    position: Vector3::new(center.x, center.y, window.z + bias),
+                    screen_barycentric: Vector3::x(),
+                    barycentric: Vector3::x(),
+                    front_facing,
+                    point_coord: (center - Vector2::new(left, top)) / size,
+                };
+                self.shade((i, j), input, &vertex.1, 1.);
+            }
+        }
     }
 }
 
-fn vertex_interpolation(triangle: &ShaderTriangle, bary_coords: nalgebra::Vector3<f64>) -> Vertex {
-    let vertex = Vertex {
-        position: triangle.v0.position * bary_coords.x
-            + triangle.v1.position * bary_coords.y
-            + triangle.v2.position * bary_coords.z,
-        normal: triangle.v0.normal * bary_coords.x
-            + triangle.v1.normal * bary_coords.y
-            + triangle.v2.normal * bary_coords.z,
+// largest change of the depth per pixel in x or y across the plane of a triangle in window
+// coordinates
+fn depth_slope(window: [Vector4<f64>; 3]) -> f64 {
+    let edges = Matrix2::new(
+        window[1].x - window[0].x,
+        window[2].x - window[0].x,
+        window[1].y - window[0].y,
+        window[2].y - window[0].y,
+    );
+    let Some(inverse) = edges.try_inverse() else {
+        return 0.;
     };
-    vertex
+    let gradient =
+        Vector2::new(window[1].z - window[0].z, window[2].z - window[0].z).transpose() * inverse;
+    gradient.abs().max()
+}
+
+// the view volume is -w <= x, y, z <= w in clip space, a point is inside a plane when its dot
+// product with the plane is positive
+fn clip_planes() -> [Vector4<f64>; 6] {
+    [
+        Vector4::new(0., 0., 1., 1.),
+        Vector4::new(0., 0., -1., 1.),
+        Vector4::new(1., 0., 0., 1.),
+        Vector4::new(-1., 0., 0., 1.),
+        Vector4::new(0., 1., 0., 1.),
+        Vector4::new(0., -1., 0., 1.),
+    ]
+}
+
+// sutherland-hodgman clipping in homogeneous coordinates, where vertex attributes can be
+// interpolated linearly
+fn clip_polygon<V: Varyings>(mut polygon: Vec<(Vector4<f64>, V)>) -> Vec<(Vector4<f64>, V)> {
+    for plane in clip_planes() {
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for k in 0..polygon.len() {
+            let (current, next) = (&polygon[k], &polygon[(k + 1) % polygon.len()]);
+            let (d0, d1) = (plane.dot(&current.0), plane.dot(&next.0));
+            if d0 >= 0. {
+                clipped.push(current.clone());
+            }
+            if (d0 >= 0.) != (d1 >= 0.) {
+                clipped.push(lerp_vertex(current, next, d0 / (d0 - d1)));
+            }
+        }
+        polygon = clipped;
+        if polygon.is_empty() {
+            break;
+        }
+    }
+    polygon
+}
+
+// liang-barsky clipping of a line in homogeneous coordinates
+fn clip_line<V: Varyings>(
+    a: &(Vector4<f64>, V),
+    b: &(Vector4<f64>, V),
+) -> Option<[(Vector4<f64>, V); 2]> {
+    let (mut t0, mut t1) = (0_f64, 1_f64);
+    for plane in clip_planes() {
+        let (d0, d1) = (plane.dot(&a.0), plane.dot(&b.0));
+        if d0 < 0. && d1 < 0. {
+            return None;
+        }
+        if d0 < 0. {
+            t0 = t0.max(d0 / (d0 - d1));
+        } else if d1 < 0. {
+            t1 = t1.min(d0 / (d0 - d1));
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some([lerp_vertex(a, b, t0), lerp_vertex(a, b, t1)])
+}
+
+fn lerp_vertex<V: Varyings>(
+    a: &(Vector4<f64>, V),
+    b: &(Vector4<f64>, V),
+    t: f64,
+) -> (Vector4<f64>, V) {
+    let weights = Vector3::new(1. - t, t, 0.);
+    (
+        a.0.lerp(&b.0, t),
+        V::interpolate([&a.1, &b.1, &b.1], &weights),
+    )
 }
 
 // this should store some globals that the shader can access
 pub struct Uniform {
-    camera_pos: Vector3<f64>,
-    camera_focal_length: f64,
-    camera_fov: f64,
-    camera_aspect_ratio: f64,
-    ambient_color: Vector3<f64>,
-    light_pos: Vector3<f64>,
-    light_color: Vector3<f64>,
+    pub view_matrix: Matrix4<f64>,
+    pub projection_matrix: Matrix4<f64>,
+    pub ambient_color: Vector3<f64>,
+    pub light: Light,
 }
 
 impl Uniform {
-    pub fn new(
-        camera_pos: Vector3<f64>,
-        camera_focal_length: f64,
-        camera_fov: f64,
-        camera_aspect_ratio: f64,
-        ambient_color: Vector3<f64>,
-        light_pos: Vector3<f64>,
-        light_color: Vector3<f64>,
-    ) -> Self {
+    // the projection follows the kind of the camera
+    pub fn new(camera: &Camera, ambient_color: Vector3<f64>, light: Light) -> Self {
         Self {
-            camera_pos,
-            camera_focal_length,
-            camera_fov,
-            camera_aspect_ratio,
+            view_matrix: camera.view_matrix(),
+            projection_matrix: camera.projection_matrix(),
             ambient_color,
-            light_pos,
-            light_color,
+            light,
         }
     }
 }
 
-// this is required for the rasterization algorithm,
-// this is the data structure that is used to store
-// the triangles with additional vertex data like normals
-#[derive(Debug)]
-pub struct ShaderTriangle {
-    pub v0: Vertex,
-    pub v1: Vertex,
-    pub v2: Vertex,
-}
-
-#[derive(Debug)]
+// the vertex attributes and varyings of the built in shaders
+#[derive(Debug, Clone)]
 pub struct Vertex {
     pub position: nalgebra::Vector3<f64>,
     pub normal: nalgebra::Vector3<f64>,
 }
 
+// vertex attributes that can be read from a mesh, for rasterize
+pub trait MeshAttributes {
+    fn from_mesh(mesh: &composites::Mesh, idx: usize) -> Self;
+}
+
+impl MeshAttributes for Vertex {
+    fn from_mesh(mesh: &composites::Mesh, idx: usize) -> Self {
+        Vertex {
+            position: mesh.positions[idx],
+            normal: mesh.normals[idx],
+        }
+    }
+}
+
+// values the vertex shader passes to the fragment shader, interpolated across the triangles
+pub trait Varyings: Clone {
+    // sum of the three values with the given weights, which add up to one
+    fn interpolate(values: [&Self; 3], weights: &Vector3<f64>) -> Self;
+}
+
+impl Varyings for () {
+    fn interpolate(_values: [&Self; 3], _weights: &Vector3<f64>) -> Self {}
+}
+
+impl Varyings for f64 {
+    fn interpolate(values: [&Self; 3], weights: &Vector3<f64>) -> Self {
+        values[0] * weights.x + values[1] * weights.y + values[2] * weights.z
+    }
+}
+
+macro_rules! impl_varyings_for_vector {
+    ($($vector:ty),*) => {
+        $(
+            impl Varyings for $vector {
+                fn interpolate(values: [&Self; 3], weights: &Vector3<f64>) -> Self {
+                    values[0] * weights.x + values[1] * weights.y + values[2] * weights.z
+                }
+            }
+        )*
+    };
+}
+
+impl_varyings_for_vector!(Vector2<f64>, Vector3<f64>, Vector4<f64>);
+
+impl Varyings for Vertex {
+    fn interpolate(values: [&Self; 3], weights: &Vector3<f64>) -> Self {
+        Vertex {
+            position: Varyings::interpolate(values.map(|v| &v.position), weights),
+            normal: Varyings::interpolate(values.map(|v| &v.normal), weights),
+        }
+    }
+}
+
+// what the rasterizer knows about a pixel covered by a triangle
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentInput {
+    // window position of the pixel center, x and y in pixels and the depth in [-1, 1] in z
+    pub position: Vector3<f64>,
+    // barycentric coordinates of the pixel center in screen space
+    pub screen_barycentric: Vector3<f64>,
+    // perspective correct barycentric coordinates, the weights of the vertices in the varyings
+    pub barycentric: Vector3<f64>,
+    // whether the triangle faces the camera according to the front face of the pipeline state,
+    // always true for lines and points that are not edges or corners of a triangle
+    pub front_facing: bool,
+    // position inside a point from (0, 0) at the top left to (1, 1), zero for triangles and lines
+    pub point_coord: Vector2<f64>,
+}
+
 pub struct Fragment {
+    // window position, the blending shader compares the depth in z
     pub position: nalgebra::Vector3<f64>,
     pub color: nalgebra::Vector3<f64>,
 }
 
-pub struct Program {
-    pub vertex_shader: fn(vertex: &Vertex, uniform: &Uniform) -> Vertex,
-    pub fragment_shader: fn(vertex: &Vertex, uniform: &Uniform) -> Fragment,
-    pub blending_shader: fn(&Fragment, Vector4<f64>, f64) -> (Vector4<f64>, f64),
+// the stages of the pipeline that can be programmed. the uniform holds the values that are
+// the same for every vertex and fragment, the attributes are the input of the vertex shader and
+// the varyings are interpolated from its output for the fragment shader
+pub trait Shader {
+    type Uniform;
+    type Attributes;
+    type Varyings: Varyings;
+
+    // position in clip space and the varyings of the vertex
+    fn vertex(
+        &self,
+        attributes: &Self::Attributes,
+        uniform: &Self::Uniform,
+    ) -> (Vector4<f64>, Self::Varyings);
+
+    fn fragment(
+        &self,
+        input: &FragmentInput,
+        varyings: &Self::Varyings,
+        uniform: &Self::Uniform,
+    ) -> Fragment;
+
+    // combine the fragment with the color and depth already in the buffers, keeps the closest
+    // fragment by default
+    fn blend(
+        &self,
+        fragment: &Fragment,
+        old_color: Vector4<f64>,
+        old_z: f64,
+    ) -> (Vector4<f64>, f64) {
+        blending_shader(fragment, old_color, old_z)
+    }
+}
+
+// a shader made of three functions or closures
+pub struct Program<A, V, U, VS, FS, BS> {
+    pub vertex_shader: VS,
+    pub fragment_shader: FS,
+    pub blending_shader: BS,
+    // the closures decide the types of the attributes, varyings and uniform
+    types: PhantomData<fn(&A, &U) -> V>,
 }
 
-// not sure where to initialize this, or make it default
-impl Program {
-    pub fn new(
-        vertex_shader: fn(&Vertex, &Uniform) -> Vertex,
-        fragment_shader: fn(&Vertex, &Uniform) -> Fragment,
-        blending_shader: fn(&Fragment, Vector4<f64>, f64) -> (Vector4<f64>, f64),
-    ) -> Program {
+impl<A, V, U, VS, FS, BS> Program<A, V, U, VS, FS, BS>
+where
+    V: Varyings,
+    VS: Fn(&A, &U) -> (Vector4<f64>, V),
+    FS: Fn(&FragmentInput, &V, &U) -> Fragment,
+    BS: Fn(&Fragment, Vector4<f64>, f64) -> (Vector4<f64>, f64),
+{
+    pub fn new(vertex_shader: VS, fragment_shader: FS, blending_shader: BS) -> Self {
         Program {
             vertex_shader,
             fragment_shader,
             blending_shader,
+            types: PhantomData,
         }
     }
 }
 
-// TODO provide the shader definitions in the main program
-// for now here
-pub fn vertex_shader(vertex: &Vertex, uniform: &Uniform) -> Vertex {
+impl<A, V, U, VS, FS, BS> Shader for Program<A, V, U, VS, FS, BS>
+where
+    V: Varyings,
+    VS: Fn(&A, &U) -> (Vector4<f64>, V),
+    FS: Fn(&FragmentInput, &V, &U) -> Fragment,
+    BS: Fn(&Fragment, Vector4<f64>, f64) -> (Vector4<f64>, f64),
+{
+    type Uniform = U;
+    type Attributes = A;
+    type Varyings = V;
+
+    fn vertex(&self, attributes: &A, uniform: &U) -> (Vector4<f64>, V) {
+        (self.vertex_shader)(attributes, uniform)
+    }
+
+    fn fragment(&self, input: &FragmentInput, varyings: &V, uniform: &U) -> Fragment {
+        (self.fragment_shader)(input, varyings, uniform)
+    }
+
+    fn blend(
+        &self,
+        fragment: &Fragment,
+        old_color: Vector4<f64>,
+        old_z: f64,
+    ) -> (Vector4<f64>, f64) {
+        (self.blending_shader)(fragment, old_color, old_z)
+    }
+}
 
+// the built in shaders, a lambert surface lit by the light of the uniform
+// returns the position in clip space and the vertex in world space for the fragment shader
+pub fn vertex_shader(vertex: &Vertex, uniform: &Uniform) -> (Vector4<f64>, Vertex) {
     // model transform: model matrix
-    let model_matrix = nalgebra::Matrix4::identity();
-
-    // view transform: camera matrix
-    let camera_matrix = nalgebra::Matrix4::new(
-        1., 0., 0., -uniform.camera_pos.x,
-        0., 1., 0., -uniform.camera_pos.y,
-        0., 0., 1., -uniform.camera_pos.z,
-        0., 0., 0., 1.,
-    );
+    let model_matrix = Matrix4::identity();
+    let world_position = model_matrix * vertex.position.push(1.);
+
+    // view transform from the camera basis, then the projection of the camera
+    let clip_position = uniform.projection_matrix * uniform.view_matrix * world_position;
 
-    // projection transform: orthographic matrix
-    // double top = uniform.camera_focal_length * tan(uniform.camera_fov / 2);
-        // double bottom = -top;
-        // double right = top * uniform.camera_aspect;
-        // double left = -right;
-        // double near = -uniform.camera_focal_length;
-        // double far = -10;
-    
-    let top = uniform.camera_focal_length * (uniform.camera_fov / 2.).tan();
-    let bottom = -top;
-    let right = top * uniform.camera_aspect_ratio;
-    let left = -right;
-    let near = -uniform.camera_focal_length;
-    let far = -10.;
-
-    // let left = -1.;
-    // let right = 1.;
-    // let bottom = -1.;
-    // let top = 1.;
-    // let near = 0.;
-    // let far = -10.;
-
-    // let projection_matrix = nalgebra::Matrix4::<f64>::identity();
-    let projection_matrix = nalgebra::Orthographic3::new(left, right, bottom, top, near, far).to_homogeneous();
-    // let projection_matrix = nalgebra::Perspective3::new(
-    //     1.,
-    //     0.3,
-    //     1.,
-    //     10.,
-    // ).to_homogeneous();
-
-
-    let final_matrix = projection_matrix * camera_matrix * model_matrix;
-    let new_pos = final_matrix * vertex.position.push(1.);
     let vertex = Vertex {
-        position: new_pos.xyz(),
+        position: world_position.xyz(),
         normal: vertex.normal,
     };
-    vertex
+    (clip_position, vertex)
 }
 
-pub fn fragment_shader(vertex: &Vertex, uniform: &Uniform) -> Fragment {
-    let light_direction = (uniform.light_pos - vertex.position).normalize();
-    let mut normal = vertex.normal.normalize();
-    if light_direction.dot(&normal) < 0. {
-        normal = -normal;
-    }
+pub fn fragment_shader(input: &FragmentInput, vertex: &Vertex, uniform: &Uniform) -> Fragment {
+    // area lights need a scene to be sampled and do not light rasterized meshes
+    let (light_direction, light_color) = uniform
+        .light
+        .incident(&vertex.position)
+        .unwrap_or((Vector3::zeros(), Vector3::zeros()));
+    // back faces are only drawn without culling, they are lit from behind
+    let normal = if input.front_facing {
+        vertex.normal.normalize()
+    } else {
+        -vertex.normal.normalize()
+    };
     let diffuse = light_direction.dot(&normal).max(0.);
-    let diffuse_color = diffuse * uniform.light_color;
+    let diffuse_color = diffuse * light_color;
     let ambient_color = uniform.ambient_color;
 
-    let fragment = Fragment {
-        position: vertex.position,
+    Fragment {
+        position: input.position,
         color: diffuse_color + ambient_color,
-    };
-    fragment
+    }
+}
+
+// draws everything in one color, for wireframes, points and boxes
+pub struct SolidColor {
+    pub color: Vector3<f64>,
+}
+
+impl Shader for SolidColor {
+    type Uniform = Uniform;
+    type Attributes = Vertex;
+    type Varyings = ();
+
+    fn vertex(&self, attributes: &Vertex, uniform: &Uniform) -> (Vector4<f64>, ()) {
+        let world_position = attributes.position.push(1.);
+        (
+            uniform.projection_matrix * uniform.view_matrix * world_position,
+            (),
+        )
+    }
+
+    fn fragment(&self, input: &FragmentInput, _varyings: &(), _uniform: &Uniform) -> Fragment {
+        Fragment {
+            position: input.position,
+            color: self.color,
+        }
+    }
 }
 
 pub fn blending_shader(
@@ -336,9 +964,206 @@ pub fn blending_shader(
 ) -> (Vector4<f64>, f64) {
     let color = Vector4::new(fragment.color.x, fragment.color.y, fragment.color.z, 1.);
     let z = fragment.position.z;
-    if z > old_z {
+    if z < old_z {
         (color, z)
     } else {
         (old_color, old_z)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 64;
+
+    // takes the corners in clip space with a single varying, drawn in the gray of the varying
+    fn program() -> impl Shader<Uniform = (), Attributes = (Vector4<f64>, f64), Varyings = f64> {
+        Program::new(
+            |vertex: &(Vector4<f64>, f64), _: &()| *vertex,
+            |input: &FragmentInput, value: &f64, _: &()| Fragment {
+                position: input.position,
+                color: Vector3::repeat(*value),
+            },
+            blending_shader,
+        )
+    }
+
+    fn draw(vertices: &[(Vector4<f64>, f64)], state: &PipelineState) -> DMatrix<Vector4<f64>> {
+        let program = program();
+        let mut frame_buffer = DMatrix::from_element(SIZE, SIZE, Vector4::zeros());
+        let mut depth_buffer = new_depth_buffer(&frame_buffer);
+        let indices: Vec<[u32; 3]> = (0..vertices.len() as u32 / 3)
+            .map(|k| [3 * k, 3 * k + 1, 3 * k + 2])
+            .collect();
+        draw_triangles(
+            vertices,
+            &indices,
+            &(),
+            &program,
+            state,
+            &mut frame_buffer,
+            &mut depth_buffer,
+        );
+        assert!(depth_buffer
+            .iter()
+            .all(|&z| z == f64::INFINITY || (-1. ..=1.).contains(&z)));
+        frame_buffer
+    }
+
+    fn drawn_pixels(frame_buffer: &DMatrix<Vector4<f64>>) -> usize {
+        frame_buffer.iter().filter(|color| color.w > 0.).count()
+    }
+
+    // counter clockwise on screen, the window y axis points down but clip space y points up
+    fn counter_clockwise(value: f64) -> Vec<(Vector4<f64>, f64)> {
+        vec![
+            (Vector4::new(-0.5, -0.5, 0., 1.), value),
+            (Vector4::new(0.5, -0.5, 0., 1.), value),
+            (Vector4::new(0., 0.5, 0., 1.), value),
+        ]
+    }
+
+    #[test]
+    fn triangles_crossing_the_near_plane_are_clipped() {
+        // the last corner is behind the camera, dividing by its negative w would flip it
+        let vertices = vec![
+            (Vector4::new(-0.5, -0.5, 0., 1.), 1.),
+            (Vector4::new(0.5, -0.5, 0., 1.), 1.),
+            (Vector4::new(0., 2., -3., -1.), 1.),
+        ];
+        let polygon = clip_polygon(vertices.clone());
+        assert!(polygon.len() >= 3);
+        for (position, _) in &polygon {
+            assert!(position.w > 0.);
+            for plane in clip_planes() {
+                assert!(plane.dot(position) >= -1e-12, "{position:?} is outside");
+            }
+        }
+
+        let state = PipelineState::default().with_cull_mode(CullMode::None);
+        let frame_buffer = draw(&vertices, &state);
+        assert!(drawn_pixels(&frame_buffer) > 0);
+        for color in frame_buffer.iter() {
+            assert!(color.iter().all(|value| value.is_finite()), "{color:?}");
+        }
+    }
+
+    #[test]
+    fn back_faces_are_culled() {
+        let front = counter_clockwise(1.);
+        let back: Vec<_> = front.iter().rev().cloned().collect();
+        // nothing is culled by default
+        assert!(drawn_pixels(&draw(&front, &PipelineState::default())) > 0);
+        assert!(drawn_pixels(&draw(&back, &PipelineState::default())) > 0);
+
+        let state = PipelineState::default().with_cull_mode(CullMode::Back);
+        assert!(drawn_pixels(&draw(&front, &state)) > 0);
+        assert_eq!(drawn_pixels(&draw(&back, &state)), 0);
+
+        let state = state.with_cull_mode(CullMode::Front);
+        assert_eq!(drawn_pixels(&draw(&front, &state)), 0);
+        assert!(drawn_pixels(&draw(&back, &state)) > 0);
+
+        // the front face decides which winding is the front
+        let state = state.with_front_face(FrontFace::Clockwise);
+        assert!(drawn_pixels(&draw(&front, &state)) > 0);
+        assert_eq!(drawn_pixels(&draw(&back, &state)), 0);
+    }
+
+    #[test]
+    fn line_and_point_modes_draw_pixels() {
+        let vertices = counter_clockwise(1.);
+        let filled = drawn_pixels(&draw(&vertices, &PipelineState::default()));
+        for algorithm in [LineAlgorithm::Bresenham, LineAlgorithm::Wu] {
+            let state = PipelineState::default()
+                .with_polygon_mode(PolygonMode::Line)
+                .with_lines(algorithm, 1.);
+            let edges = drawn_pixels(&draw(&vertices, &state));
+            assert!(
+                edges > 0 && edges < filled,
+                "{algorithm:?}: {edges} of {filled}"
+            );
+        }
+
+        // the corners are at pixel corners, so every point covers 3 x 3 pixels
+        let state = PipelineState::default()
+            .with_polygon_mode(PolygonMode::Point)
+            .with_point_size(3.);
+        assert_eq!(drawn_pixels(&draw(&vertices, &state)), 27);
+    }
+
+    #[test]
+    fn constant_varyings_stay_constant() {
+        // very different depths make the perspective correction matter
+        let value = 0.37;
+        let vertices: Vec<_> = counter_clockwise(value)
+            .into_iter()
+            .zip([1., 3., 7.])
+            .map(|((position, value), w)| (position * w, value))
+            .collect();
+        let frame_buffer = draw(&vertices, &PipelineState::default());
+        assert!(drawn_pixels(&frame_buffer) > 0);
+        for color in frame_buffer.iter().filter(|color| color.w > 0.) {
+            assert!(
+                (color.xyz() - Vector3::repeat(value)).abs().max() < 1e-12,
+                "{color:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn partly_covered_pixels_keep_their_depth() {
+        let program = program();
+        let state = PipelineState::default().with_lines(LineAlgorithm::Wu, 1.);
+        let mut frame_buffer = DMatrix::from_element(SIZE, SIZE, Vector4::zeros());
+        let mut depth_buffer = new_depth_buffer(&frame_buffer);
+        // a shallow line in front, most of its pixels are only partly covered
+        let line = [
+            (Vector4::new(-0.8, -0.1, -0.5, 1.), 1.),
+            (Vector4::new(0.8, 0.2, -0.5, 1.), 1.),
+        ];
+        draw_lines(
+            &line,
+            &[[0, 1]],
+            &(),
+            &program,
+            &state,
+            &mut frame_buffer,
+            &mut depth_buffer,
+        );
+        let partial: Vec<_> = (0..SIZE)
+            .flat_map(|i| (0..SIZE).map(move |j| (i, j)))
+            .filter(|&pixel| frame_buffer[pixel].w > 0. && frame_buffer[pixel].w < 1.)
+            .collect();
+        assert!(!partial.is_empty());
+        for &pixel in &partial {
+            assert_eq!(depth_buffer[pixel], f64::INFINITY, "{pixel:?}");
+        }
+
+        // a quad behind the line still shows through its soft edges
+        let quad = [
+            (Vector4::new(-1., -1., 0.5, 1.), 0.5),
+            (Vector4::new(1., -1., 0.5, 1.), 0.5),
+            (Vector4::new(1., 1., 0.5, 1.), 0.5),
+            (Vector4::new(-1., 1., 0.5, 1.), 0.5),
+        ];
+        draw_triangles(
+            &quad,
+            &[[0, 1, 2], [0, 2, 3]],
+            &(),
+            &program,
+            &state,
+            &mut frame_buffer,
+            &mut depth_buffer,
+        );
+        for &pixel in &partial {
+            assert_eq!(
+                frame_buffer[pixel],
+                Vector4::new(0.5, 0.5, 0.5, 1.),
+                "{pixel:?}"
+            );
+            assert_eq!(depth_buffer[pixel], 0.5);
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{
+    pathtracer,
+    primitives::{Camera, Scene},
+    sampling::{PixelSampler, SamplePattern},
+    utils,
+};
+use nalgebra::{DMatrix, Vector2, Vector4};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+// image buffer indexed by (x, y) with the origin at the top-left corner
+pub type Framebuffer = DMatrix<Vector4<f64>>;
+
+// algorithm that computes the color seen along a camera ray
+#[derive(Debug, Clone, Copy)]
+pub enum Integrator {
+    // direct light, mirror reflection and refraction only, recursing up to max_bounce
+    Whitted,
+    // global illumination, paths longer than rr_depth are terminated with russian roulette
+    PathTracer { max_depth: usize, rr_depth: usize },
+}
+
+pub struct RenderSettings {
+    pub integrator: Integrator,
+    // recursion depth of the whitted integrator
+    pub max_bounce: usize,
+    // shadow rays per area light and shading point of the whitted integrator
+    pub light_samples: usize,
+    // camera rays per pixel, combined with the reconstruction filter
+    pub samples_per_pixel: usize,
+    // distribution of the samples over the filter support and the lens
+    pub sample_pattern: SamplePattern,
+    pub filter: Filter,
+    // edge length of the square tiles handed out to the worker threads
+    pub tile_size: usize,
+    // number of worker threads, 0 uses all available cores
+    pub threads: usize,
+}
+
+impl RenderSettings {
+    pub fn new(max_bounce: usize) -> Self {
+        Self {
+            integrator: Integrator::Whitted,
+            max_bounce,
+            light_samples: 16,
+            samples_per_pixel: 1,
+            sample_pattern: SamplePattern::Sobol,
+            filter: Filter::default(),
+            tile_size: 16,
+            threads: 0,
+        }
+    }
+
+    fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            self.threads
+        } else {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+// pixel reconstruction filter, separable in x and y. offsets are measured in pixels from
+// the pixel center
+#[derive(Debug, Clone, Copy)]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    Gaussian { radius: f64, alpha: f64 },
+    // b = c = 1/3 is the recommended choice
+    Mitchell { radius: f64, b: f64, c: f64 },
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. } => radius,
+        }
+    }
+
+    pub fn evaluate(&self, offset: &Vector2<f64>) -> f64 {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        match *self {
+            Filter::Box { radius } => {
+                if x.abs() <= radius {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            Filter::Tent { radius } => (radius - x.abs()).max(0.),
+            Filter::Gaussian { radius, alpha } => {
+                // shifted down so that it reaches zero at the edge of the support
+                ((-alpha * x * x).exp() - (-alpha * radius * radius).exp()).max(0.)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = (2. * x / radius).abs();
+                if x > 2. {
+                    0.
+                } else if x > 1. {
+                    ((-b - 6. * c) * x.powi(3)
+                        + (6. * b + 30. * c) * x.powi(2)
+                        + (-12. * b - 48. * c) * x
+                        + (8. * b + 24. * c))
+                        / 6.
+                } else {
+                    ((12. - 9. * b - 6. * c) * x.powi(3)
+                        + (-18. + 12. * b + 6. * c) * x.powi(2)
+                        + (6. - 2. * b))
+                        / 6.
+                }
+            }
+        }
+    }
+}
+
+impl Default for Filter {
+    // a single pixel wide box, a plain average of the samples
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+fn split_into_tiles(width: usize, height: usize, tile_size: usize) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(tile_size) {
+        for x in (0..width).step_by(tile_size) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+// weighted average of the samples spread over the filter support around the pixel center
+fn render_pixel(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    i: usize,
+    j: usize,
+) -> Vector4<f64> {
+    let mut sampler = PixelSampler::new(settings.sample_pattern, settings.samples_per_pixel, i, j);
+    let radius = settings.filter.radius();
+    let mut color = Vector4::zeros();
+    let mut weight_sum = 0.;
+    for sample in 0..sampler.samples_per_pixel() {
+        // a single sample goes through the pixel center, jittering it only adds noise
+        let offset = if sampler.samples_per_pixel() == 1 {
+            Vector2::zeros()
+        } else {
+            (2. * sampler.get_2d(sample, 0) - Vector2::new(1., 1.)) * radius
+        };
+        let weight = settings.filter.evaluate(&offset);
+        let lens = sampler.get_2d(sample, 1);
+        let ray = camera.sample_ray(i as f64 + 0.5 + offset.x, j as f64 + 0.5 + offset.y, &lens);
+        // dimensions 0 and 1 are the image and lens positions
+        let mut samples = sampler.stream(sample, 2);
+        let sample_color = match settings.integrator {
+            Integrator::Whitted => utils::shoot_ray(
+                &ray,
+                scene,
+                settings.max_bounce,
+                settings.light_samples,
+                &mut samples,
+            ),
+            Integrator::PathTracer {
+                max_depth,
+                rr_depth,
+            } => pathtracer::trace_path(&ray, scene, max_depth, rr_depth, &mut samples),
+        };
+        color += weight * sample_color;
+        weight_sum += weight;
+    }
+    // negative lobes can cancel out all the weight
+    if weight_sum.abs() > 1e-12 {
+        color / weight_sum
+    } else {
+        Vector4::zeros()
+    }
+}
+
+// every pixel only depends on its own coordinates, so the image does not depend on
+// which thread rendered which tile
+fn render_tile(
+    scene: &Scene,
+    camera: &Camera,
+    settings: &RenderSettings,
+    tile: &Tile,
+) -> Vec<Vector4<f64>> {
+    let mut colors = Vec::with_capacity(tile.width * tile.height);
+    for j in tile.y..tile.y + tile.height {
+        for i in tile.x..tile.x + tile.width {
+            colors.push(render_pixel(scene, camera, settings, i, j));
+        }
+    }
+    colors
+}
+
+// render the scene as seen from the camera, splitting the image into tiles that are
+// picked up by a pool of worker threads
+pub fn render(scene: &Scene, camera: &Camera, settings: &RenderSettings) -> Framebuffer {
+    let tiles = split_into_tiles(camera.width, camera.height, settings.tile_size);
+    let next_tile = AtomicUsize::new(0);
+
+    let rendered_tiles: Vec<(usize, Vec<Vector4<f64>>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..settings.thread_count().min(tiles.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut rendered = Vec::new();
+                    loop {
+                        let tile_idx = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if tile_idx >= tiles.len() {
+                            break;
+                        }
+                        rendered.push((
+                            tile_idx,
+                            render_tile(scene, camera, settings, &tiles[tile_idx]),
+                        ));
+                    }
+                    rendered
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    let mut frame_buffer = Framebuffer::from_element(camera.width, camera.height, Vector4::zeros());
+    for (tile_idx, colors) in rendered_tiles {
+        let tile = &tiles[tile_idx];
+        for (k, color) in colors.into_iter().enumerate() {
+            frame_buffer[(tile.x + k % tile.width, tile.y + k / tile.width)] = color;
+        }
+    }
+    frame_buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lights::Light;
+    use crate::primitives::{CameraKind, Material, Parallelogram, SceneObject, Sphere};
+    use nalgebra::Vector3;
+    use std::f64::consts::FRAC_PI_4;
+
+    fn test_scene() -> Scene {
+        let materials = vec![
+            Material::new(
+                Vector3::new(0.7, 0.7, 0.7),
+                Vector3::new(0.2, 0.2, 0.2),
+                64.,
+                Vector3::zeros(),
+                Vector3::zeros(),
+            ),
+            Material::new(
+                Vector3::zeros(),
+                Vector3::new(0.2, 0.2, 0.2),
+                256.,
+                Vector3::new(1., 1., 1.),
+                Vector3::new(1., 1., 1.),
+            )
+            .with_refractive_index(1.5),
+            Material::new(
+                Vector3::zeros(),
+                Vector3::zeros(),
+                1.,
+                Vector3::zeros(),
+                Vector3::zeros(),
+            )
+            .with_emission(Vector3::new(4., 4., 4.)),
+        ];
+        let objects = vec![
+            SceneObject::new(Box::new(Sphere::new(Vector3::new(-0.6, 0., 0.), 0.5)), 0),
+            SceneObject::new(Box::new(Sphere::new(Vector3::new(0.6, 0., 0.), 0.5)), 1),
+            SceneObject::new(
+                Box::new(Parallelogram::new(
+                    Vector3::new(-5., -0.5, -5.),
+                    Vector3::new(-5., -0.5, 5.),
+                    Vector3::new(5., -0.5, -5.),
+                )),
+                0,
+            ),
+            SceneObject::new(
+                Box::new(Parallelogram::new(
+                    Vector3::new(-0.5, 2., -0.5),
+                    Vector3::new(0.5, 2., -0.5),
+                    Vector3::new(-0.5, 2., 0.5),
+                )),
+                2,
+            ),
+        ];
+        let lights = vec![Light::point(
+            Vector3::new(2., 3., 2.),
+            Vector3::new(8., 8., 8.),
+        )];
+        Scene::new(objects, materials, lights, Vector3::new(0.05, 0.05, 0.05))
+    }
+
+    #[test]
+    fn thread_count_does_not_change_the_image() {
+        let scene = test_scene();
+        let camera = Camera::look_at(
+            FRAC_PI_4,
+            1.,
+            23,
+            17,
+            Vector3::new(0., 1., 4.),
+            Vector3::zeros(),
+            Vector3::new(0., 1., 0.),
+            CameraKind::PERSPECTIVE,
+        );
+        for integrator in [
+            Integrator::Whitted,
+            Integrator::PathTracer {
+                max_depth: 4,
+                rr_depth: 2,
+            },
+        ] {
+            let mut settings = RenderSettings::new(3);
+            settings.integrator = integrator;
+            settings.samples_per_pixel = 4;
+            settings.light_samples = 2;
+            // tiles that do not divide the image
+            settings.tile_size = 5;
+            settings.threads = 1;
+            let single = render(&scene, &camera, &settings);
+            settings.threads = 7;
+            let multi = render(&scene, &camera, &settings);
+            assert!(single.iter().any(|color| color.w > 0.));
+            assert_eq!(single, multi, "{integrator:?}");
+        }
+    }
+
+    #[test]
+    fn filters_vanish_outside_their_radius() {
+        let filters = [
+            Filter::Box { radius: 0.5 },
+            Filter::Tent { radius: 1. },
+            Filter::Gaussian {
+                radius: 1.5,
+                alpha: 2.,
+            },
+            Filter::Mitchell {
+                radius: 2.,
+                b: 1. / 3.,
+                c: 1. / 3.,
+            },
+        ];
+        for filter in filters {
+            let r = filter.radius();
+            assert!(filter.evaluate(&Vector2::zeros()) > 0., "{filter:?}");
+            for offset in [
+                Vector2::new(r * 1.001, 0.),
+                Vector2::new(0., -r * 1.001),
+                Vector2::new(-r * 1.5, r * 1.5),
+                Vector2::new(r * 3., r * 0.1),
+            ] {
+                assert_eq!(filter.evaluate(&offset), 0., "{filter:?} at {offset:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use nalgebra::{Vector2, Vector3};
+use std::f64::consts::PI;
+
+// small deterministic random number generator (splitmix64), seeded per pixel so that
+// renders do not depend on the order in which pixels are processed
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn for_pixel(i: usize, j: usize) -> Self {
+        Self::new(hash(((i as u64) << 32) ^ j as u64))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        hash(self.state)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn next_2d(&mut self) -> Vector2<f64> {
+        Vector2::new(self.next_f64(), self.next_f64())
+    }
+}
+
+// finalizer of splitmix64, scrambles the bits of x
+pub fn hash(x: u64) -> u64 {
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// map a point of the unit square to the unit disk, keeping strata intact (shirley-chiu)
+pub fn sample_concentric_disk(u: &Vector2<f64>) -> Vector2<f64> {
+    let offset = 2. * u - Vector2::new(1., 1.);
+    if offset.x == 0. && offset.y == 0. {
+        return Vector2::zeros();
+    }
+
+    let (r, theta) = if offset.x.abs() > offset.y.abs() {
+        (offset.x, PI / 4. * (offset.y / offset.x))
+    } else {
+        (offset.y, PI / 2. - PI / 4. * (offset.x / offset.y))
+    };
+    r * Vector2::new(theta.cos(), theta.sin())
+}
+
+// map a point of the unit square uniformly onto a regular polygon inscribed in the unit circle
+pub fn sample_polygon(u: &Vector2<f64>, sides: usize, rotation: f64) -> Vector2<f64> {
+    let sides = sides.max(3);
+    // pick one of the triangles fanning out from the center and reuse the rest of u.x
+    let scaled = u.x * sides as f64;
+    let sector = (scaled as usize).min(sides - 1);
+    let ux = scaled - sector as f64;
+
+    let angle = 2. * PI / sides as f64;
+    let start = rotation + sector as f64 * angle;
+    let p1 = Vector2::new(start.cos(), start.sin());
+    let p2 = Vector2::new((start + angle).cos(), (start + angle).sin());
+
+    // uniform point in the triangle (center, p1, p2)
+    let su = ux.sqrt();
+    su * ((1. - u.y) * p1 + u.y * p2)
+}
+
+// how the 2d sample points of a pixel are distributed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplePattern {
+    // uniform random points
+    Independent,
+    // one jittered point per cell of a grid over the unit square
+    Stratified,
+    // halton sequence with a random shift per pixel
+    Halton,
+    // sobol (0, 2) sequence with random digit scrambling per pixel
+    Sobol,
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// generates the samples of a single pixel. every dimension is a separate 2d point set, for
+// example the position on the image and the position on the lens, which are decorrelated
+// from each other. dimensions the pattern can not provide fall back to random points
+pub struct PixelSampler {
+    pattern: SamplePattern,
+    samples_per_pixel: usize,
+    seed: u64,
+    rng: Rng,
+}
+
+impl PixelSampler {
+    pub fn new(pattern: SamplePattern, samples_per_pixel: usize, i: usize, j: usize) -> Self {
+        let rng = Rng::for_pixel(i, j);
+        Self {
+            pattern,
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed: rng.state,
+            rng,
+        }
+    }
+
+    pub fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    // random numbers for anything that is not part of the pattern
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    pub fn get_2d(&mut self, sample_index: usize, dimension: usize) -> Vector2<f64> {
+        let seed = hash(self.seed ^ hash(dimension as u64 + 1));
+        match self.pattern {
+            SamplePattern::Independent => self.rng.next_2d(),
+            SamplePattern::Stratified => {
+                let nx = (self.samples_per_pixel as f64).sqrt().ceil() as usize;
+                let cells = nx * self.samples_per_pixel.div_ceil(nx);
+                // shuffle which sample gets which cell, separately for every dimension
+                let cell = permute(sample_index % cells, cells, seed);
+                stratified_sample(cell, self.samples_per_pixel, &self.rng.next_2d())
+            }
+            SamplePattern::Halton if 2 * dimension + 1 < PRIMES.len() => {
+                let index = sample_index as u64;
+                let shift = Vector2::new(
+                    (hash(seed) >> 11) as f64 / (1u64 << 53) as f64,
+                    (hash(seed + 1) >> 11) as f64 / (1u64 << 53) as f64,
+                );
+                let point = Vector2::new(
+                    radical_inverse(index, PRIMES[2 * dimension]),
+                    radical_inverse(index, PRIMES[2 * dimension + 1]),
+                );
+                // cranley-patterson rotation
+                (point + shift).map(|x| x.fract())
+            }
+            SamplePattern::Sobol => {
+                // shuffling the order within a pixel keeps the point set, but decorrelates dimensions
+                let index = if dimension == 0 || sample_index >= self.samples_per_pixel {
+                    sample_index
+                } else {
+                    permute(sample_index, self.samples_per_pixel, seed)
+                } as u32;
+                let scramble = hash(seed);
+                let x = index.reverse_bits() ^ scramble as u32;
+                let y = sobol_second_dimension(index) ^ (scramble >> 32) as u32;
+                Vector2::new(
+                    x as f64 / (1u64 << 32) as f64,
+                    y as f64 / (1u64 << 32) as f64,
+                )
+            }
+            SamplePattern::Halton => self.rng.next_2d(),
+        }
+    }
+}
+
+// digits of the index in the given base, mirrored around the decimal point
+pub fn radical_inverse(mut index: u64, base: u64) -> f64 {
+    let inv_base = 1. / base as f64;
+    let mut inv_base_n = 1.;
+    let mut reversed = 0u64;
+    while index > 0 {
+        reversed = reversed * base + index % base;
+        inv_base_n *= inv_base;
+        index /= base;
+    }
+    (reversed as f64 * inv_base_n).min(1. - f64::EPSILON)
+}
+
+// second dimension of the sobol sequence, its generator matrix is the pascal matrix mod 2
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+// element i of a pseudo random permutation of 0..n (kensler, correlated multi-jittered sampling)
+pub fn permute(i: usize, n: usize, seed: u64) -> usize {
+    let (mut i, l, p) = (i as u32, n as u32, seed as u32);
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p) % l) as usize
+}
+
+// consecutive dimensions of one sample of a pixel, handed out in the order they are requested
+pub struct SampleStream<'a> {
+    sampler: &'a mut PixelSampler,
+    sample: usize,
+    dimension: usize,
+}
+
+impl PixelSampler {
+    pub fn stream(&mut self, sample: usize, first_dimension: usize) -> SampleStream<'_> {
+        SampleStream {
+            sampler: self,
+            sample,
+            dimension: first_dimension,
+        }
+    }
+}
+
+impl SampleStream<'_> {
+    pub fn next_2d(&mut self) -> Vector2<f64> {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        self.sampler.get_2d(self.sample, dimension)
+    }
+
+    // single decisions like picking a lobe are not worth a dimension of the pattern
+    pub fn next_1d(&mut self) -> f64 {
+        self.sampler.rng.next_f64()
+    }
+}
+
+pub fn sample_uniform_sphere(u: &Vector2<f64>) -> Vector3<f64> {
+    let z = 1. - 2. * u.x;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * u.y;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// barycentric coordinates of a uniformly distributed point in a triangle
+pub fn sample_uniform_triangle(u: &Vector2<f64>) -> Vector3<f64> {
+    let su0 = u.x.sqrt();
+    let b1 = 1. - su0;
+    let b2 = u.y * su0;
+    Vector3::new(1. - b1 - b2, b1, b2)
+}
+
+// direction in the hemisphere around +z with density cos(theta) / pi
+pub fn sample_cosine_hemisphere(u: &Vector2<f64>) -> Vector3<f64> {
+    let d = sample_concentric_disk(u);
+    let z = (1. - d.norm_squared()).max(0.).sqrt();
+    Vector3::new(d.x, d.y, z)
+}
+
+// jittered point in cell `index` of a grid with at least `count` cells over the unit square
+pub fn stratified_sample(index: usize, count: usize, jitter: &Vector2<f64>) -> Vector2<f64> {
+    let nx = (count.max(1) as f64).sqrt().ceil() as usize;
+    let ny = count.max(1).div_ceil(nx);
+    Vector2::new(
+        ((index % nx) as f64 + jitter.x) / nx as f64,
+        ((index / nx % ny) as f64 + jitter.y) / ny as f64,
+    )
+}
+
+// piecewise constant density over [0, 1) proportional to the given non negative values
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len().max(1);
+        let integral = func.iter().sum::<f64>() / n as f64;
+        // fall back to a uniform density if there is nothing to sample
+        let func = if integral > 0. { func } else { vec![1.; n] };
+        let integral = func.iter().sum::<f64>() / n as f64;
+
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.);
+        for value in &func {
+            cdf.push(cdf.last().unwrap() + value / (integral * n as f64));
+        }
+        *cdf.last_mut().unwrap() = 1.;
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.func.is_empty()
+    }
+
+    // average of the values
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // returns the sampled point, its density and the index of the piece it falls into
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let index = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(self.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let du = if width > 0. {
+            (u - self.cdf[index]) / width
+        } else {
+            0.
+        };
+        (
+            (index as f64 + du) / self.len() as f64,
+            self.pdf(index),
+            index,
+        )
+    }
+
+    // density of the points in the given piece
+    pub fn pdf(&self, index: usize) -> f64 {
+        self.func[index] / self.integral
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const PATTERNS: [SamplePattern; 3] = [
+        SamplePattern::Stratified,
+        SamplePattern::Halton,
+        SamplePattern::Sobol,
+    ];
+
+    #[test]
+    fn samples_stay_in_the_unit_square() {
+        for pattern in PATTERNS {
+            for spp in [1, 7, 16, 64] {
+                for (i, j) in [(0, 0), (3, 11), (640, 480)] {
+                    let mut sampler = PixelSampler::new(pattern, spp, i, j);
+                    for dimension in 0..4 {
+                        for index in 0..spp {
+                            let u = sampler.get_2d(index, dimension);
+                            assert!(
+                                (0. ..1.).contains(&u.x) && (0. ..1.).contains(&u.y),
+                                "{pattern:?} sample {index} of dimension {dimension} is {u:?}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_samples_cover_every_stratum() {
+        // square counts fill the grid exactly, the others leave some of the last row empty
+        for spp in [4usize, 9, 12, 16, 10] {
+            let nx = (spp as f64).sqrt().ceil() as usize;
+            let ny = spp.div_ceil(nx);
+            let mut sampler = PixelSampler::new(SamplePattern::Stratified, spp, 5, 2);
+            for dimension in 0..3 {
+                let strata: HashSet<(usize, usize)> = (0..spp)
+                    .map(|index| {
+                        let u = sampler.get_2d(index, dimension);
+                        ((u.x * nx as f64) as usize, (u.y * ny as f64) as usize)
+                    })
+                    .collect();
+                assert_eq!(strata.len(), spp, "{spp} samples share a stratum");
+                if nx * ny == spp {
+                    assert!((0..nx).all(|x| (0..ny).all(|y| strata.contains(&(x, y)))));
+                }
+            }
+        }
+    }
+}
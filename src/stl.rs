@@ -0,0 +1,233 @@
+use crate::composites::{parse_vector3, Mesh, MeshLoadError, ParseErrorKind};
+use nalgebra::Vector3;
+use std::fs;
+use std::path::Path;
+
+// 80 byte header and the number of triangles
+const BINARY_HEADER_SIZE: usize = 84;
+// normal, three corners and an attribute byte count
+const BINARY_TRIANGLE_SIZE: usize = 50;
+
+// binary files may start with "solid" as well, so the size decides first and ascii files must
+// also begin with text
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() >= BINARY_HEADER_SIZE {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == BINARY_HEADER_SIZE + count * BINARY_TRIANGLE_SIZE {
+            return true;
+        }
+    }
+    let is_text = data
+        .iter()
+        .take(512)
+        .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace());
+    !(is_text && data.trim_ascii_start().starts_with(b"solid"))
+}
+
+fn read_binary(data: &[u8], path: &Path) -> Result<Vec<[Vector3<f64>; 3]>, MeshLoadError> {
+    let Some(count) = data.get(80..BINARY_HEADER_SIZE) else {
+        let kind = ParseErrorKind::WrongCount {
+            what: "header bytes",
+            expected: BINARY_HEADER_SIZE,
+            found: data.len(),
+        };
+        return Err(MeshLoadError::binary(path, 0, kind));
+    };
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+
+    let records = data[BINARY_HEADER_SIZE..].chunks_exact(BINARY_TRIANGLE_SIZE);
+    if records.len() < count {
+        let kind = ParseErrorKind::WrongCount {
+            what: "triangles",
+            expected: count,
+            found: records.len(),
+        };
+        // the offset is the start of the first triangle that is cut off
+        let offset = BINARY_HEADER_SIZE + records.len() * BINARY_TRIANGLE_SIZE;
+        return Err(MeshLoadError::binary(path, offset, kind));
+    }
+    // the facet normal is skipped, the winding of the corners gives the same orientation
+    Ok(records
+        .take(count)
+        .map(|record| {
+            let value = |k: usize| {
+                let bytes = &record[12 + 4 * k..][..4];
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+            };
+            [0, 1, 2].map(|corner| {
+                Vector3::new(
+                    value(3 * corner),
+                    value(3 * corner + 1),
+                    value(3 * corner + 2),
+                )
+            })
+        })
+        .collect())
+}
+
+// facets with more than three corners are split into triangle fans
+fn read_ascii(data: &[u8], path: &Path) -> Result<Vec<[Vector3<f64>; 3]>, MeshLoadError> {
+    let text = String::from_utf8_lossy(data);
+    let mut triangles = Vec::new();
+    let mut corners = Vec::new();
+
+    for (idx, line) in text.lines().enumerate() {
+        let mut tokens = line.split_ascii_whitespace();
+        match tokens.next() {
+            Some("vertex") => corners.push(
+                parse_vector3(&mut tokens)
+                    .map_err(|kind| MeshLoadError::parse(path, idx + 1, kind))?,
+            ),
+            Some("endloop") => {
+                if corners.len() < 3 {
+                    let kind = ParseErrorKind::WrongCount {
+                        what: "facet vertices",
+                        expected: 3,
+                        found: corners.len(),
+                    };
+                    return Err(MeshLoadError::parse(path, idx + 1, kind));
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+                corners.clear();
+            }
+            // solid, facet normal, outer loop, endfacet and endsolid carry nothing we need
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
+
+impl Mesh {
+    // ascii and binary stl files. stl has no shared vertices, so every triangle keeps its own
+    // corners and is shaded flat, which suits the hard edges of cad models
+    pub fn from_stl_file(path: &str) -> Result<Self, MeshLoadError> {
+        let data = fs::read(path).map_err(|error| MeshLoadError::io(path, error))?;
+        let path = Path::new(path);
+        let triangles = if is_binary(&data) {
+            read_binary(&data, path)?
+        } else {
+            read_ascii(&data, path)?
+        };
+
+        let positions = triangles.iter().flatten().copied().collect();
+        let indices = (0..triangles.len() as u32)
+            .map(|idx| [3 * idx, 3 * idx + 1, 3 * idx + 2])
+            .collect();
+        Ok(Self::new(positions, indices))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composites::tests::{binary_error, load_error, parse_error, temp_file};
+
+    fn load_stl(name: &str, contents: impl AsRef<[u8]>) -> Result<Mesh, MeshLoadError> {
+        Mesh::from_stl_file(&temp_file(name, contents))
+    }
+
+    // binary file whose header starts with "solid" like the ones many exporters write
+    fn binary_triangles(triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut data = b"solid exported".to_vec();
+        data.resize(80, b' ');
+        data.extend((triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            data.extend([0; 12]);
+            for value in triangle.iter().flatten() {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend([0; 2]);
+        }
+        data
+    }
+
+    #[test]
+    fn stl_ascii_facets() {
+        let mesh = load_stl(
+            "stl_ascii.stl",
+            "solid quad\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+             vertex 1 1 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid quad\n",
+        )
+        .unwrap();
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [3, 4, 5]]);
+        assert_eq!(mesh.positions[4], Vector3::new(1., 1., 0.));
+    }
+
+    #[test]
+    fn stl_binary_with_solid_header() {
+        let data = binary_triangles(&[[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]]);
+        let mesh = load_stl("stl_binary.stl", data).unwrap();
+        assert_eq!(
+            mesh.positions,
+            vec![Vector3::zeros(), Vector3::x(), Vector3::y()]
+        );
+        assert_eq!(mesh.normals[0], Vector3::z());
+    }
+
+    #[test]
+    fn stl_binary_truncated() {
+        let mut data = binary_triangles(&[[[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]; 2]);
+        data.truncate(data.len() - 10);
+        assert_eq!(
+            binary_error(load_stl("stl_truncated.stl", &data)),
+            (
+                BINARY_HEADER_SIZE + BINARY_TRIANGLE_SIZE,
+                ParseErrorKind::WrongCount {
+                    what: "triangles",
+                    expected: 2,
+                    found: 1,
+                }
+            )
+        );
+
+        // the triangle count is cut off, its first byte is not text so the file stays binary
+        data.truncate(82);
+        assert_eq!(
+            binary_error(load_stl("stl_short_header.stl", &data)),
+            (
+                0,
+                ParseErrorKind::WrongCount {
+                    what: "header bytes",
+                    expected: BINARY_HEADER_SIZE,
+                    found: 82,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn stl_ascii_errors() {
+        let result = load_stl(
+            "stl_number.stl",
+            "solid s\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 O 0\n",
+        );
+        assert_eq!(
+            parse_error(result),
+            (5, ParseErrorKind::InvalidNumber("O".to_owned()))
+        );
+        let result = load_stl(
+            "stl_corners.stl",
+            "solid s\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nendloop\n",
+        );
+        assert_eq!(
+            parse_error(result),
+            (
+                6,
+                ParseErrorKind::WrongCount {
+                    what: "facet vertices",
+                    expected: 3,
+                    found: 2,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn stl_missing_file() {
+        let error = load_error(Mesh::from_stl_file("does/not/exist.stl"));
+        assert!(matches!(error, MeshLoadError::Io { .. }));
+    }
+}
@@ -17,7 +17,7 @@ fn dot_grid_gradient(ix: i32, iy: i32, x: f64, y: f64, grid: &[[Vector2<f64>; 4]
 }
 
 // Compute Perlin noise at coordinates x, y
-fn perlin(x: f64, y: f64, grid: &[[Vector2<f64>; 4]; 4]) -> f64 {
+pub fn perlin(x: f64, y: f64, grid: &[[Vector2<f64>; 4]; 4]) -> f64 {
     // TODO: Determine grid cell coordinates x0, y0
     let x0 = x.floor() as i32;
     let x1 = x0 + 1;
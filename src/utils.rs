@@ -1,108 +1,205 @@
 use crate::{
-    primitives::{self, HitPoint, Ray, Scene},
-    textures,
+    lights::LightSample,
+    primitives::{HitPoint, Ray, Scene, SceneObject},
+    sampling::{self, SampleStream},
 };
-use nalgebra::{Vector3, Vector4};
+use nalgebra::{Vector2, Vector3, Vector4};
 
-pub fn find_closest_point(
-    ray: &Ray,
-    objects: &Vec<Box<dyn primitives::Object>>,
-) -> Option<(usize, HitPoint)> {
-    let mut closest_point = None;
-
-    for (index, object) in objects.iter().enumerate() {
-        if let Some(hit_point) = object.intersects(ray) {
-            match closest_point {
-                None => {
-                    closest_point = Some((index, hit_point));
-                }
-                Some((_, ref closest_hit_point)) => {
-                    if hit_point.t < closest_hit_point.t {
-                        closest_point = Some((index, hit_point));
-                    }
-                }
-            }
-        }
+// returns the closest hit along the ray together with the object that was hit
+pub fn find_closest_point<'a>(ray: &Ray, scene: &'a Scene) -> Option<(&'a SceneObject, HitPoint)> {
+    scene.bvh.closest_hit(ray, |idx, ray| {
+        let object = &scene.objects[idx];
+        object
+            .shape
+            .intersects(ray)
+            .map(|hit_point| (hit_point.t, (object, hit_point)))
+    })
+}
+
+// perceived brightness of a linear rgb color
+pub fn luminance(color: &Vector3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+// orthonormal tangents of a unit vector (duff et al.)
+pub fn coordinate_system(n: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let sign = 1f64.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        Vector3::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+// mirror the direction about the normal
+pub fn reflect(direction: &Vector3<f64>, normal: &Vector3<f64>) -> Vector3<f64> {
+    (direction - 2. * normal.dot(direction) * normal).normalize()
+}
+
+// bend the direction through the surface following snell's law, eta is the ratio of the indices of
+// refraction of the incident and transmitted media and the normal must face against the direction.
+// returns None on total internal reflection
+pub fn refract(direction: &Vector3<f64>, normal: &Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+    let cos_i = -normal.dot(direction).clamp(-1., 1.);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i).max(0.);
+    if sin2_t >= 1. {
+        return None;
     }
-    closest_point
+    let cos_t = (1. - sin2_t).sqrt();
+    Some((eta * direction + (eta * cos_i - cos_t) * normal).normalize())
 }
 
-// check if the light is visible
-pub fn is_light_visible(
-    light: &primitives::Light,
-    point: &Vector3<f64>,
-    objects: &Vec<Box<dyn primitives::Object>>,
-) -> bool {
-    let light_ray = Ray::new(*point, (light.position - point).normalize());
-    let ans = find_closest_point(&light_ray, objects);
-    match ans {
-        Some((_, hit_point)) => {
-            let distance_to_light = (light.position - point).norm();
-            let distance_to_hit_point = (hit_point.point - point).norm();
-            distance_to_hit_point > distance_to_light
-        }
-        None => true,
+// exact fresnel reflectance for unpolarized light at the boundary of two dielectrics,
+// cos_i is the cosine between the incident ray and the normal on the incident side
+pub fn fresnel_dielectric(cos_i: f64, eta_i: f64, eta_t: f64) -> f64 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin_t = eta_i / eta_t * (1. - cos_i * cos_i).max(0.).sqrt();
+    if sin_t >= 1. {
+        // total internal reflection
+        return 1.;
     }
+    let cos_t = (1. - sin_t * sin_t).max(0.).sqrt();
+
+    let r_parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let r_perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+// check if anything in the scene blocks the ray before t_max
+pub fn is_occluded(ray: &Ray, t_max: f64, scene: &Scene) -> bool {
+    let ray = Ray {
+        t_max: ray.t_max.min(t_max),
+        ..*ray
+    };
+    scene.bvh.any_hit(&ray, |idx| {
+        scene.objects[idx].shape.occluded(&ray, ray.t_max)
+    })
+}
+
+// check if the sampled point on a light is visible from the hit point
+pub fn is_light_visible(light_sample: &LightSample, hit_point: &HitPoint, scene: &Scene) -> bool {
+    let light_ray = match light_sample.position {
+        Some(position) => hit_point.spawn_ray_to(&position),
+        // directional lights are infinitely far away
+        None => hit_point.spawn_ray(light_sample.direction),
+    };
+    !is_occluded(&light_ray, light_ray.t_max, scene)
 }
 
+// area lights are sampled light_samples times on a jittered grid to get soft shadows
 pub fn shoot_ray(
     ray: &Ray,
     scene: &Scene,
-    material: &primitives::Material,
     max_bounce: usize,
+    light_samples: usize,
+    samples: &mut SampleStream,
 ) -> Vector4<f64> {
-    let ans = find_closest_point(ray, &scene.objects);
+    let ans = find_closest_point(ray, scene);
     match ans {
-        Some((object_idx, hit_point)) => {
-            let intersection = hit_point.point;
-            let normal = hit_point.normal;
+        Some((object, hit_point)) => {
+            let material = scene.material(object.material);
+            let normal = hit_point.shading_normal;
             let ambient_color = scene.ambient_color;
 
             // diffuse and specular
             let mut lights_color = Vector3::new(0., 0., 0.);
             for light in &scene.lights {
-                if is_light_visible(light, &intersection, &scene.objects) {
-                let mut diffuse_color = material.diffuse_color;
-                // procedural texture
-                // if object_idx == 4 {
-                //     // Compute UV coodinates for the point on the sphere
-                //     let xyz = intersection - Vector3::new(-2., 0.4, 1.);
-                //     let tu = (xyz.z / 1.).acos() / std::f64::consts::PI;
-                //     let tv =
-                //         (std::f64::consts::PI + xyz.y.atan2(xyz.x)) / (2. * std::f64::consts::PI);
-                //     diffuse_color = textures::procedural_texture(tu, tv);
-                // }
-
-                let light_vector = (light.position - intersection).normalize();
-                let bisector_direction = (light_vector - ray.direction).normalize();
-                let diffuse_coeff = normal.dot(&light_vector).max(0.);
-                let specular_coeff = normal.dot(&bisector_direction).max(0.).powf(256.);
-
-                let diffuse = diffuse_coeff * diffuse_color;
-                let specular = specular_coeff * material.specular_color;
-
-                let attenuation = (light.position - intersection).norm_squared(); // attenuation is square of distance
-                lights_color += light.color.component_mul(&(diffuse + specular)) / attenuation;
+                let sample_count = if light.is_delta() {
+                    1
+                } else {
+                    light_samples.max(1)
+                };
+                let jitter = Vector2::new(samples.next_1d(), samples.next_1d());
+                for k in 0..sample_count {
+                    let u = sampling::stratified_sample(k, sample_count, &jitter);
+                    let Some(light_sample) = light.sample(scene, &hit_point.point, &u) else {
+                        continue;
+                    };
+                    if !is_light_visible(&light_sample, &hit_point, scene) {
+                        continue;
+                    }
+                    let light_vector = light_sample.direction;
+                    let bisector_direction = (light_vector - ray.direction).normalize();
+                    let diffuse_coeff = normal.dot(&light_vector).max(0.);
+                    let specular_coeff = normal
+                        .dot(&bisector_direction)
+                        .max(0.)
+                        .powf(material.specular_exponent);
+
+                    let diffuse = diffuse_coeff * material.diffuse_color;
+                    let specular = specular_coeff * material.specular_color;
+
+                    // the radiance of point and spot lights is already attenuated
+                    lights_color += light_sample.radiance.component_mul(&(diffuse + specular))
+                        / (light_sample.pdf * sample_count as f64);
                 }
             }
 
-            // reflection
+            // reflection and refraction
             let mut reflection_color = Vector3::new(0., 0., 0.);
+            let mut refraction_color = Vector3::new(0., 0., 0.);
             if max_bounce > 0 {
-                let reflection_direction =
-                    (ray.direction - 2. * normal.dot(&ray.direction) * normal).normalize();
-                let adjusted_origin = intersection + 1e-5 * reflection_direction;
-                let reflection_ray = Ray::new(adjusted_origin, reflection_direction);
-                let refl_color = shoot_ray(&reflection_ray, &scene, &material, max_bounce - 1);
-                reflection_color = material.reflection_color.component_mul(&refl_color.xyz());
+                // for dielectrics the fresnel term splits the energy between the two rays,
+                // opaque materials reflect with their reflection color only
+                let mut reflectance = 1.;
+                if material.is_transparent() {
+                    // the normal faces the ray, so front_face tells us which side the ray comes from
+                    let (eta_i, eta_t) = if hit_point.front_face {
+                        (1., material.refractive_index)
+                    } else {
+                        (material.refractive_index, 1.)
+                    };
+                    let cos_i = -normal.dot(&ray.direction);
+                    reflectance = fresnel_dielectric(cos_i, eta_i, eta_t);
+
+                    // total internal reflection leaves nothing to transmit
+                    if let Some(refraction_direction) =
+                        refract(&ray.direction, &normal, eta_i / eta_t)
+                    {
+                        let refraction_ray = hit_point.spawn_ray(refraction_direction);
+                        let refr_color = shoot_ray(
+                            &refraction_ray,
+                            scene,
+                            max_bounce - 1,
+                            light_samples,
+                            samples,
+                        );
+                        refraction_color = (1. - reflectance)
+                            * material.refraction_color.component_mul(&refr_color.xyz());
+                    }
+                }
+
+                let reflection_direction = reflect(&ray.direction, &normal);
+                let reflection_ray = hit_point.spawn_ray(reflection_direction);
+                let refl_color = shoot_ray(
+                    &reflection_ray,
+                    scene,
+                    max_bounce - 1,
+                    light_samples,
+                    samples,
+                );
+                reflection_color =
+                    reflectance * material.reflection_color.component_mul(&refl_color.xyz());
             }
 
-            let color = ambient_color + lights_color + reflection_color;
+            let emission = if hit_point.front_face {
+                material.emission
+            } else {
+                Vector3::zeros()
+            };
+
+            let color =
+                ambient_color + emission + lights_color + reflection_color + refraction_color;
             Vector4::new(color.x, color.y, color.z, 1.)
         }
-        None => {
+        None => match &scene.background {
+            Some(background) => {
+                let color = background.radiance(&ray.direction);
+                Vector4::new(color.x, color.y, color.z, 1.)
+            }
             // no intersection, can return a None and handle default color on its own, but keeping for parity with cpp
-            Vector4::new(0., 0., 0., 0.)
-        }
+            None => Vector4::new(0., 0., 0., 0.),
+        },
     }
 }